@@ -0,0 +1,188 @@
+// Tape cell types.
+
+use core::fmt;
+
+/// The type of a single tape cell.
+///
+/// Cells use wrapping arithmetic, so every width behaves like the
+/// classic 8-bit cells modulo its own size.
+pub trait Cell: Copy + Default + Eq + fmt::Debug {
+    /// The width of the cell in bits.
+    const BITS: u32;
+
+    /// Wrapping addition.
+    fn wrapping_add(self, rhs: Self) -> Self;
+    /// Wrapping subtraction.
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    /// Wrapping multiplication.
+    fn wrapping_mul(self, rhs: Self) -> Self;
+    /// Convert an IR operand to a cell, wrapping it modulo the cell size.
+    fn from_i32(value: i32) -> Self;
+    /// Convert an input byte to a cell.
+    fn from_byte(byte: u8) -> Self;
+    /// Widen the cell to a `u64`.
+    fn to_u64(self) -> u64;
+
+    /// Find the index of the first zero cell.
+    fn find_zero(haystack: &[Self]) -> Option<usize> {
+        haystack.iter().position(|&cell| cell == Self::default())
+    }
+
+    /// Find the index of the last zero cell.
+    fn rfind_zero(haystack: &[Self]) -> Option<usize> {
+        haystack.iter().rposition(|&cell| cell == Self::default())
+    }
+}
+
+macro_rules! impl_cell {
+    ($($ty:ty),*) => {$(
+        impl Cell for $ty {
+            const BITS: u32 = <$ty>::BITS;
+
+            #[inline]
+            fn wrapping_add(self, rhs: Self) -> Self {
+                <$ty>::wrapping_add(self, rhs)
+            }
+
+            #[inline]
+            fn wrapping_sub(self, rhs: Self) -> Self {
+                <$ty>::wrapping_sub(self, rhs)
+            }
+
+            #[inline]
+            fn wrapping_mul(self, rhs: Self) -> Self {
+                <$ty>::wrapping_mul(self, rhs)
+            }
+
+            #[inline]
+            fn from_i32(value: i32) -> Self {
+                value as $ty
+            }
+
+            #[inline]
+            fn from_byte(byte: u8) -> Self {
+                byte.into()
+            }
+
+            #[inline]
+            fn to_u64(self) -> u64 {
+                self.into()
+            }
+        }
+    )*};
+}
+
+impl_cell!(u16, u32, u64);
+
+impl Cell for u8 {
+    const BITS: u32 = u8::BITS;
+
+    #[inline]
+    fn wrapping_add(self, rhs: Self) -> Self {
+        u8::wrapping_add(self, rhs)
+    }
+
+    #[inline]
+    fn wrapping_sub(self, rhs: Self) -> Self {
+        u8::wrapping_sub(self, rhs)
+    }
+
+    #[inline]
+    fn wrapping_mul(self, rhs: Self) -> Self {
+        u8::wrapping_mul(self, rhs)
+    }
+
+    #[inline]
+    fn from_i32(value: i32) -> Self {
+        value as u8
+    }
+
+    #[inline]
+    fn from_byte(byte: u8) -> Self {
+        byte
+    }
+
+    #[inline]
+    fn to_u64(self) -> u64 {
+        self.into()
+    }
+
+    fn find_zero(haystack: &[Self]) -> Option<usize> {
+        find_zero_byte(haystack)
+    }
+
+    fn rfind_zero(haystack: &[Self]) -> Option<usize> {
+        rfind_zero_byte(haystack)
+    }
+}
+
+/// How the `.` instruction turns a cell into output bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputEncoding {
+    /// Output the low byte of the cell.
+    #[default]
+    Truncate,
+    /// Output the cell as a UTF-8 encoded Unicode code point, values that
+    /// aren't code points come out as U+FFFD REPLACEMENT CHARACTER.
+    CodePoint,
+}
+
+impl OutputEncoding {
+    /// Encode a cell into `buffer`, returning the encoded bytes.
+    pub fn encode<C: Cell>(self, cell: C, buffer: &mut [u8; 4]) -> &[u8] {
+        match self {
+            Self::Truncate => {
+                buffer[0] = cell.to_u64() as u8;
+                &buffer[..1]
+            }
+            Self::CodePoint => {
+                let ch = u32::try_from(cell.to_u64())
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                ch.encode_utf8(buffer).as_bytes()
+            }
+        }
+    }
+}
+
+const WORD: usize = size_of::<usize>();
+const LO_BITS: usize = usize::from_ne_bytes([0x01; WORD]);
+const HI_BITS: usize = usize::from_ne_bytes([0x80; WORD]);
+
+/// Check whether any byte of a word is zero.
+#[inline]
+const fn has_zero_byte(word: usize) -> bool {
+    word.wrapping_sub(LO_BITS) & !word & HI_BITS != 0
+}
+
+/// Find the index of the first zero byte, a word at a time.
+fn find_zero_byte(haystack: &[u8]) -> Option<usize> {
+    let mut chunks = haystack.chunks_exact(WORD);
+    for (chunk_idx, chunk) in chunks.by_ref().enumerate() {
+        let word = usize::from_ne_bytes(chunk.try_into().unwrap());
+        if has_zero_byte(word) {
+            let idx = chunk.iter().position(|&byte| byte == 0)?;
+            return Some(chunk_idx * WORD + idx);
+        }
+    }
+
+    let remainder = chunks.remainder();
+    let offset = haystack.len() - remainder.len();
+    let idx = remainder.iter().position(|&byte| byte == 0)?;
+    Some(offset + idx)
+}
+
+/// Find the index of the last zero byte, a word at a time.
+fn rfind_zero_byte(haystack: &[u8]) -> Option<usize> {
+    let mut chunks = haystack.rchunks_exact(WORD);
+    for (chunk_idx, chunk) in chunks.by_ref().enumerate() {
+        let word = usize::from_ne_bytes(chunk.try_into().unwrap());
+        if has_zero_byte(word) {
+            let idx = chunk.iter().rposition(|&byte| byte == 0)?;
+            return Some(haystack.len() - (chunk_idx + 1) * WORD + idx);
+        }
+    }
+
+    chunks.remainder().iter().rposition(|&byte| byte == 0)
+}
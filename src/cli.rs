@@ -0,0 +1,293 @@
+// Command-line argument parsing for the `bfc` binary.
+
+use std::{fmt, path::PathBuf};
+
+use bfc::{Dialect, EofBehavior, OptLevel, TapePolicy};
+
+/// The help text printed by `bfc help`.
+pub const USAGE: &str = "\
+Usage: bfc <COMMAND> [OPTIONS] [FILE]
+
+Commands:
+  run        Run a program (the default when no command is given)
+  check      Check a program for errors without running it
+  fmt        Print a program with comments removed and loops indented
+  dump-ir    Print the optimized IR of a program
+  compile    Translate a program to another language
+  debug      Step through a program with breakpoints and watchpoints, use
+             -O0 to step through it one instruction at a time
+  repl       Run lines of code interactively on one tape, after running
+             the program if one is given
+  help       Print this help
+
+The program is read from FILE, from stdin if FILE is `-`, or given inline
+with `-e`.
+
+Options:
+  -e <CODE>             Use CODE as the program
+  -O0, -O1, -O2         The optimization level [default: -O2]
+  --tape-size <CELLS>   Use a fixed tape of CELLS cells instead of a
+                        growing one
+  --cell-width <BITS>   The width of a cell: 8, 16, 32 or 64 [default: 8]
+  --eof <BEHAVIOR>      What `,` stores at the end of input: unchanged,
+                        zero or minus-one [default: unchanged]
+  --debug-dump          Treat `#` as an instruction that prints the cells
+                        around the pointer to stderr
+  --input-separator     Treat the first `!` as the end of the code, with
+                        the rest of the source as the program's input
+  --jit                 Compile the program to machine code to run it, on
+                        x86-64 Linux
+  --target <TARGET>     The language to compile to: bf, c, rust, x86_64
+                        (GNU assembly for Linux) or elf (an x86-64 Linux
+                        executable) [default: bf]
+  -o <FILE>             Write the compiled program to FILE instead of stdout
+  -h, --help            Print this help
+  -V, --version         Print the version
+";
+
+/// The subcommand to execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Check,
+    Fmt,
+    DumpIr,
+    Compile,
+    Debug,
+    Repl,
+    Help,
+    Version,
+}
+
+/// Where the program's source comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    Stdin,
+    Inline(String),
+}
+
+/// The width of a tape cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellWidth {
+    #[default]
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+/// The language `bfc compile` translates to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    /// Brainfuck itself, with everything but the instructions removed.
+    #[default]
+    Bf,
+    /// A standalone C program.
+    C,
+    /// A standalone Rust program.
+    Rust,
+    /// x86-64 assembly for Linux.
+    X86_64,
+    /// An x86-64 Linux executable.
+    Elf,
+}
+
+/// The parsed command line.
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub command: Command,
+    pub source: Option<Source>,
+    pub opt_level: OptLevel,
+    /// The size of a fixed tape, the tape grows on demand if not set.
+    pub tape_size: Option<u32>,
+    pub cell_width: CellWidth,
+    pub eof_behavior: EofBehavior,
+    pub dialect: Dialect,
+    /// Whether to run the program with the JIT.
+    pub jit: bool,
+    pub target: Target,
+    pub output: Option<PathBuf>,
+}
+
+/// An invalid command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Args {
+    /// Parse the arguments, without the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, UsageError> {
+        let mut args = args.into_iter().peekable();
+        let mut parsed = Self {
+            command: Command::Run,
+            source: None,
+            opt_level: OptLevel::default(),
+            tape_size: None,
+            cell_width: CellWidth::default(),
+            eof_behavior: EofBehavior::default(),
+            dialect: Dialect::default(),
+            jit: false,
+            target: Target::default(),
+            output: None,
+        };
+
+        // A bare file argument runs it, as `bfc FILE` always has.
+        let command = match args.peek().map(String::as_str) {
+            Some("run") => Some(Command::Run),
+            Some("check") => Some(Command::Check),
+            Some("fmt") => Some(Command::Fmt),
+            Some("dump-ir") => Some(Command::DumpIr),
+            Some("compile") => Some(Command::Compile),
+            Some("debug") => Some(Command::Debug),
+            Some("repl") => Some(Command::Repl),
+            Some("help") => Some(Command::Help),
+            _ => None,
+        };
+        if let Some(command) = command {
+            parsed.command = command;
+            args.next();
+        }
+
+        let mut only_files = false;
+        while let Some(arg) = args.next() {
+            if only_files || arg == "-" || !arg.starts_with('-') {
+                let source = match arg.as_str() {
+                    "-" => Source::Stdin,
+                    _ => Source::File(arg.into()),
+                };
+                parsed.set_source(source)?;
+                continue;
+            }
+
+            // Allow both `--flag value` and `--flag=value`.
+            let (flag, mut inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = |args: &mut dyn Iterator<Item = String>| {
+                inline
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| UsageError(format!("`{flag}` needs a value")))
+            };
+
+            match flag.as_str() {
+                "--" => only_files = true,
+                "-h" | "--help" => parsed.command = Command::Help,
+                "-V" | "--version" => parsed.command = Command::Version,
+                "-O0" => parsed.opt_level = OptLevel::O0,
+                "-O1" => parsed.opt_level = OptLevel::O1,
+                "-O2" => parsed.opt_level = OptLevel::O2,
+                "-e" => {
+                    let code = value(&mut args)?;
+                    parsed.set_source(Source::Inline(code))?;
+                }
+                "--tape-size" => {
+                    let size = value(&mut args)?;
+                    parsed.tape_size = match size.parse() {
+                        Ok(0) | Err(_) => {
+                            return Err(UsageError(format!("invalid tape size `{size}`")));
+                        }
+                        Ok(size) => Some(size),
+                    };
+                }
+                "--cell-width" => {
+                    parsed.cell_width = match value(&mut args)?.as_str() {
+                        "8" => CellWidth::U8,
+                        "16" => CellWidth::U16,
+                        "32" => CellWidth::U32,
+                        "64" => CellWidth::U64,
+                        width => {
+                            return Err(UsageError(format!(
+                                "invalid cell width `{width}`, expected 8, 16, 32 or 64"
+                            )));
+                        }
+                    };
+                }
+                "--eof" => {
+                    parsed.eof_behavior = match value(&mut args)?.as_str() {
+                        "unchanged" => EofBehavior::Unchanged,
+                        "zero" | "0" => EofBehavior::Zero,
+                        "minus-one" | "-1" => EofBehavior::MinusOne,
+                        eof => {
+                            return Err(UsageError(format!(
+                                "invalid EOF behavior `{eof}`, expected unchanged, zero or minus-one"
+                            )));
+                        }
+                    };
+                }
+                "--debug-dump" => parsed.dialect.debug_dump = true,
+                "--input-separator" => parsed.dialect.input_separator = true,
+                "--jit" => parsed.jit = true,
+                "--target" => {
+                    parsed.target = match value(&mut args)?.as_str() {
+                        "bf" => Target::Bf,
+                        "c" => Target::C,
+                        "rust" => Target::Rust,
+                        "x86_64" => Target::X86_64,
+                        "elf" => Target::Elf,
+                        target => {
+                            return Err(UsageError(format!("unknown target `{target}`")));
+                        }
+                    };
+                }
+                "-o" | "--output" => parsed.output = Some(value(&mut args)?.into()),
+                _ if flag.starts_with("-O") => {
+                    return Err(UsageError(format!("unknown optimization level `{flag}`")));
+                }
+                _ => return Err(UsageError(format!("unknown option `{flag}`"))),
+            }
+
+            if inline.is_some() {
+                return Err(UsageError(format!("`{flag}` doesn't take a value")));
+            }
+        }
+
+        let needs_source = !matches!(
+            parsed.command,
+            Command::Repl | Command::Help | Command::Version
+        );
+        if needs_source && parsed.source.is_none() {
+            return Err(UsageError(
+                "no program given, pass a file, `-` or `-e <CODE>`".into(),
+            ));
+        }
+
+        // Compiled programs read stdin, so input after `!` would be lost.
+        let compiled = parsed.command == Command::Compile && parsed.target != Target::Bf;
+        if compiled && parsed.dialect.input_separator {
+            return Err(UsageError(
+                "`--input-separator` only works with `--target bf`".into(),
+            ));
+        }
+
+        Ok(parsed)
+    }
+
+    /// The initial tape size and the tape policy to run programs with.
+    pub fn tape(&self) -> (u32, TapePolicy) {
+        match self.tape_size {
+            Some(size) => (size, TapePolicy::Error),
+            // Start with the standard Brainfuck memory size, and grow past
+            // it for programs that need more.
+            None => (30_000, TapePolicy::Grow { max_size: None }),
+        }
+    }
+
+    /// Set the program's source, there may only be one.
+    fn set_source(&mut self, source: Source) -> Result<(), UsageError> {
+        if self.source.is_some() {
+            return Err(UsageError("more than one program given".into()));
+        }
+        self.source = Some(source);
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+// A tiny x86-64 assembler, encoding just the instructions the backends
+// need.
+
+use alloc::vec::Vec;
+
+/// A general purpose register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reg(u8);
+
+pub const RAX: Reg = Reg(0);
+pub const RCX: Reg = Reg(1);
+pub const RDX: Reg = Reg(2);
+pub const RBX: Reg = Reg(3);
+pub const RSP: Reg = Reg(4);
+pub const RBP: Reg = Reg(5);
+pub const RSI: Reg = Reg(6);
+pub const RDI: Reg = Reg(7);
+pub const R12: Reg = Reg(12);
+pub const R13: Reg = Reg(13);
+pub const R14: Reg = Reg(14);
+pub const R15: Reg = Reg(15);
+
+/// A memory operand, `[base + index * scale + disp]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mem {
+    base: Option<Reg>,
+    index: Option<(Reg, u8)>,
+    disp: i32,
+}
+
+impl Mem {
+    /// The memory at an absolute address.
+    pub fn abs(addr: u32) -> Self {
+        Self {
+            base: None,
+            index: None,
+            disp: addr as i32,
+        }
+    }
+
+    /// The memory `disp` bytes after the address in `base`.
+    pub fn base(base: Reg, disp: i32) -> Self {
+        Self {
+            base: Some(base),
+            index: None,
+            disp,
+        }
+    }
+
+    /// The memory at `base + index * scale`, where `scale` is 1, 2, 4 or 8.
+    pub fn indexed(base: Reg, index: Reg, scale: u8) -> Self {
+        Self {
+            base: Some(base),
+            index: Some((index, scale)),
+            disp: 0,
+        }
+    }
+}
+
+/// A register or memory operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Mem(Mem),
+}
+
+impl From<Reg> for Operand {
+    fn from(reg: Reg) -> Self {
+        Self::Reg(reg)
+    }
+}
+
+impl From<Mem> for Operand {
+    fn from(mem: Mem) -> Self {
+        Self::Mem(mem)
+    }
+}
+
+/// A condition for jumps and conditional moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    /// Unsigned below.
+    B = 0x2,
+    /// Unsigned above or equal.
+    Ae = 0x3,
+    E = 0x4,
+    Ne = 0x5,
+    /// Unsigned above.
+    A = 0x7,
+    /// Negative.
+    S = 0x8,
+    /// Not negative.
+    Ns = 0x9,
+    /// Signed less or equal.
+    Le = 0xe,
+}
+
+/// A position in the code to jump to, which may be bound later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// Assembles instructions into machine code.
+///
+/// Operand widths are given in bits. Jumps and calls always use 32-bit
+/// displacements, patched in by [`Assembler::finish`].
+#[derive(Debug, Default)]
+pub struct Assembler {
+    code: Vec<u8>,
+    /// The offset each label is bound to.
+    labels: Vec<Option<usize>>,
+    /// The offsets of displacements to patch, and the label they're to.
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    /// The number of bytes assembled so far.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    /// Append raw bytes.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// Create a new, unbound label.
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Bind a label to the current position.
+    pub fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    /// The offset a label is bound to, if it is yet.
+    pub fn offset(&self, label: Label) -> Option<usize> {
+        self.labels[label.0]
+    }
+
+    /// Patch in the displacements of jumps and calls, returning the code.
+    ///
+    /// Panics if a label that's jumped to was never bound.
+    pub fn finish(mut self) -> Vec<u8> {
+        for &(at, label) in &self.fixups {
+            let target = self.labels[label.0].expect("jump to an unbound label");
+            let disp = target as i64 - (at as i64 + 4);
+            self.code[at..at + 4].copy_from_slice(&(disp as i32).to_le_bytes());
+        }
+        self.code
+    }
+
+    /// `mov reg, imm`.
+    pub fn mov_ri(&mut self, reg: Reg, imm: u64) {
+        let rex = 0x40 | (reg.0 >> 3);
+        match u32::try_from(imm) {
+            // Writing the low half zero extends.
+            Ok(imm) => {
+                if rex != 0x40 {
+                    self.code.push(rex);
+                }
+                self.code.push(0xb8 + (reg.0 & 7));
+                self.code.extend_from_slice(&imm.to_le_bytes());
+            }
+            Err(_) => {
+                self.code
+                    .extend_from_slice(&[rex | 0x08, 0xb8 + (reg.0 & 7)]);
+                self.code.extend_from_slice(&imm.to_le_bytes());
+            }
+        }
+    }
+
+    /// `mov dst, src`.
+    pub fn mov(&mut self, width: u32, dst: impl Into<Operand>, src: Reg) {
+        self.encode(
+            width,
+            &[if width == 8 { 0x88 } else { 0x89 }],
+            src,
+            dst.into(),
+        );
+    }
+
+    /// `mov dst, [src]`.
+    pub fn load(&mut self, width: u32, dst: Reg, src: Mem) {
+        self.encode(
+            width,
+            &[if width == 8 { 0x8a } else { 0x8b }],
+            dst,
+            src.into(),
+        );
+    }
+
+    /// `mov dst, imm`, with `imm` truncated to the width, or sign extended
+    /// from 32 bits for 64-bit operands.
+    pub fn mov_mi(&mut self, width: u32, dst: Mem, imm: i32) {
+        self.encode(
+            width,
+            &[if width == 8 { 0xc6 } else { 0xc7 }],
+            Reg(0),
+            dst.into(),
+        );
+        self.immediate(width, imm);
+    }
+
+    /// Load a `width` bit value into a register, zero extending it.
+    pub fn load_zx(&mut self, width: u32, dst: Reg, src: Mem) {
+        match width {
+            8 => self.encode(32, &[0x0f, 0xb6], dst, src.into()),
+            16 => self.encode(32, &[0x0f, 0xb7], dst, src.into()),
+            _ => self.load(width, dst, src),
+        }
+    }
+
+    /// `lea dst, [src]`.
+    pub fn lea(&mut self, dst: Reg, src: Mem) {
+        self.encode(64, &[0x8d], dst, src.into());
+    }
+
+    /// `add dst, src`.
+    pub fn add(&mut self, width: u32, dst: impl Into<Operand>, src: Reg) {
+        self.encode(
+            width,
+            &[if width == 8 { 0x00 } else { 0x01 }],
+            src,
+            dst.into(),
+        );
+    }
+
+    /// `sub dst, src`.
+    pub fn sub(&mut self, width: u32, dst: Reg, src: Reg) {
+        self.encode(width, &[0x29], src, dst.into());
+    }
+
+    /// `xor dst, src`.
+    pub fn xor(&mut self, width: u32, dst: Reg, src: Reg) {
+        self.encode(width, &[0x31], src, dst.into());
+    }
+
+    /// `cmp lhs, rhs`.
+    pub fn cmp(&mut self, width: u32, lhs: Reg, rhs: impl Into<Operand>) {
+        self.encode(width, &[0x3b], lhs, rhs.into());
+    }
+
+    /// `test lhs, rhs`.
+    pub fn test(&mut self, width: u32, lhs: Reg, rhs: Reg) {
+        self.encode(width, &[0x85], rhs, lhs.into());
+    }
+
+    /// `add dst, imm`, with `imm` truncated to the width, or sign extended
+    /// from 32 bits for 64-bit operands.
+    pub fn add_i(&mut self, width: u32, dst: impl Into<Operand>, imm: i32) {
+        self.group1(0, width, dst.into(), imm);
+    }
+
+    /// `sub dst, imm`, like [`Assembler::add_i`].
+    pub fn sub_i(&mut self, width: u32, dst: impl Into<Operand>, imm: i32) {
+        self.group1(5, width, dst.into(), imm);
+    }
+
+    /// `cmp lhs, imm`, like [`Assembler::add_i`].
+    pub fn cmp_i(&mut self, width: u32, lhs: impl Into<Operand>, imm: i32) {
+        self.group1(7, width, lhs.into(), imm);
+    }
+
+    /// `imul dst, src, imm`, on 64 bits.
+    pub fn imul_i(&mut self, dst: Reg, src: Reg, imm: i32) {
+        self.encode(64, &[0x69], dst, src.into());
+        self.immediate(64, imm);
+    }
+
+    /// `neg reg`, on 64 bits.
+    pub fn neg(&mut self, reg: Reg) {
+        self.encode(64, &[0xf7], Reg(3), reg.into());
+    }
+
+    /// `div reg`, dividing rdx:rax by it.
+    pub fn div(&mut self, reg: Reg) {
+        self.encode(64, &[0xf7], Reg(6), reg.into());
+    }
+
+    /// `cmov<cond> dst, src`, on 64 bits.
+    pub fn cmov(&mut self, cond: Cond, dst: Reg, src: Reg) {
+        self.encode(64, &[0x0f, 0x40 | cond as u8], dst, src.into());
+    }
+
+    /// `cdqe`, sign extending eax to rax.
+    pub fn cdqe(&mut self) {
+        self.code.extend_from_slice(&[0x48, 0x98]);
+    }
+
+    /// `rep movsb`, copying rcx bytes from rsi to rdi.
+    pub fn rep_movsb(&mut self) {
+        self.code.extend_from_slice(&[0xf3, 0xa4]);
+    }
+
+    /// `syscall`.
+    pub fn syscall(&mut self) {
+        self.code.extend_from_slice(&[0x0f, 0x05]);
+    }
+
+    /// `push reg`.
+    pub fn push(&mut self, reg: Reg) {
+        if reg.0 >= 8 {
+            self.code.push(0x41);
+        }
+        self.code.push(0x50 + (reg.0 & 7));
+    }
+
+    /// `pop reg`.
+    pub fn pop(&mut self, reg: Reg) {
+        if reg.0 >= 8 {
+            self.code.push(0x41);
+        }
+        self.code.push(0x58 + (reg.0 & 7));
+    }
+
+    /// `call reg`.
+    pub fn call_r(&mut self, reg: Reg) {
+        self.encode(32, &[0xff], Reg(2), reg.into());
+    }
+
+    /// `jmp reg`.
+    pub fn jmp_r(&mut self, reg: Reg) {
+        self.encode(32, &[0xff], Reg(4), reg.into());
+    }
+
+    /// `ret`.
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+
+    /// `jmp label`.
+    pub fn jmp(&mut self, label: Label) {
+        self.code.push(0xe9);
+        self.fixup(label);
+    }
+
+    /// `j<cond> label`.
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+        self.code.extend_from_slice(&[0x0f, 0x80 | cond as u8]);
+        self.fixup(label);
+    }
+
+    /// `call label`.
+    pub fn call(&mut self, label: Label) {
+        self.code.push(0xe8);
+        self.fixup(label);
+    }
+
+    /// Leave room for the displacement to a label.
+    fn fixup(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.code.extend_from_slice(&[0; 4]);
+    }
+
+    /// The `add`, `sub` and `cmp` instructions with an immediate, picked by
+    /// `ext`.
+    fn group1(&mut self, ext: u8, width: u32, dst: Operand, imm: i32) {
+        self.encode(
+            width,
+            &[if width == 8 { 0x80 } else { 0x81 }],
+            Reg(ext),
+            dst,
+        );
+        self.immediate(width, imm);
+    }
+
+    /// Append an immediate for a `width` bit operation.
+    fn immediate(&mut self, width: u32, imm: i32) {
+        let bytes = imm.to_le_bytes();
+        match width {
+            8 => self.code.push(bytes[0]),
+            16 => self.code.extend_from_slice(&bytes[..2]),
+            _ => self.code.extend_from_slice(&bytes),
+        }
+    }
+
+    /// Encode an instruction with a ModRM byte, with `reg` in its reg field
+    /// (or an opcode extension) and `rm` as its other operand.
+    fn encode(&mut self, width: u32, opcode: &[u8], reg: Reg, rm: Operand) {
+        if width == 16 {
+            self.code.push(0x66);
+        }
+        let mut rex = 0x40 | (u8::from(width == 64) << 3) | ((reg.0 >> 3) << 2);
+        match rm {
+            Operand::Reg(rm) => rex |= rm.0 >> 3,
+            Operand::Mem(mem) => {
+                rex |= mem.index.map_or(0, |(index, _)| index.0 >> 3) << 1;
+                rex |= mem.base.map_or(0, |base| base.0 >> 3);
+            }
+        }
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+        self.code.extend_from_slice(opcode);
+
+        let reg = (reg.0 & 7) << 3;
+        match rm {
+            Operand::Reg(rm) => self.code.push(0xc0 | reg | (rm.0 & 7)),
+            Operand::Mem(mem) => {
+                // Always use a 32-bit displacement, or just the displacement
+                // without a base.
+                let mode = if mem.base.is_some() { 0x80 } else { 0 };
+                let base = mem.base.map_or(5, |base| base.0 & 7);
+                // A SIB byte is needed for an index, no base, or a base of
+                // rsp or r12, with 0b100 as no index.
+                if mem.index.is_some() || base == 4 || mem.base.is_none() {
+                    let (index, scale) = mem.index.map_or((4, 0), |(index, scale)| {
+                        (index.0 & 7, scale.trailing_zeros() as u8)
+                    });
+                    self.code.push(mode | reg | 4);
+                    self.code.push((scale << 6) | (index << 3) | base);
+                } else {
+                    self.code.push(mode | reg | base);
+                }
+                self.code.extend_from_slice(&mem.disp.to_le_bytes());
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// The C backend.
+
+use core::fmt::{self, Write};
+
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+
+use super::{CodegenOptions, DUMP_RADIUS, split_sign};
+use crate::{
+    cell::Cell,
+    ir::{IR, Op},
+    vm::EofBehavior,
+};
+
+/// The part of the program before `main`, `{tape_size}` and `{cell}` are
+/// filled in.
+const PRELUDE: &str = r#"/* Generated by bfc. */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#define TAPE_SIZE {tape_size}
+
+typedef {cell} cell;
+
+cell tape[TAPE_SIZE];
+
+/* The index of the cell `offset` cells away from `ptr`, exiting if that's
+   off the tape. */
+static inline size_t at(size_t ptr, int64_t offset, unsigned op) {
+    int64_t idx = (int64_t)ptr + offset;
+    if (idx < 0 || idx >= TAPE_SIZE) {
+        fflush(stdout);
+        fprintf(stderr, "Error: tape %s at op %u: pointer moved to cell %lld.\n",
+                idx < 0 ? "underflow" : "overflow", op, (long long)idx);
+        exit(1);
+    }
+    return (size_t)idx;
+}
+"#;
+
+/// Prints the cells around the pointer for `#`, only included if the
+/// program uses it. `{radius}` is filled in.
+const DUMP: &str = r##"
+/* Print the cells around the pointer to stderr. */
+static inline void dump(size_t ptr, unsigned op) {
+    size_t start = ptr < {radius} ? 0 : ptr - {radius};
+    size_t end = ptr + {radius} + 1 < TAPE_SIZE ? ptr + {radius} + 1 : TAPE_SIZE;
+    fflush(stdout);
+    fprintf(stderr, "# at op %u: ptr %zu | cells %zu..=%zu:", op, ptr, start, end - 1);
+    for (size_t i = start; i < end; i++) {
+        fprintf(stderr, i == ptr ? " [%llu]" : " %llu", (unsigned long long)tape[i]);
+    }
+    fputc('\n', stderr);
+}
+"##;
+
+/// Compile an IR to a standalone C program with a tape of `C` cells.
+///
+/// The program reads input with `getchar` and writes output with
+/// `putchar`, truncating cells to bytes.
+pub fn compile<C: Cell>(ir: &IR, options: &CodegenOptions) -> String {
+    let mut out = String::new();
+    out.push_str(
+        &PRELUDE
+            .replace("{tape_size}", &options.tape_size.to_string())
+            .replace("{cell}", &format!("uint{}_t", C::BITS)),
+    );
+    if ir.tokens.contains(&Op::Debug) {
+        out.push_str(&DUMP.replace("{radius}", &DUMP_RADIUS.to_string()));
+    }
+
+    out.push_str("\nint main(void) {\n");
+    if !ir.tokens.is_empty() {
+        out.push_str("    size_t p = 0;\n");
+    }
+    if ir
+        .tokens
+        .iter()
+        .any(|op| matches!(op, Op::InByte | Op::In { .. }))
+    {
+        out.push_str("    int c;\n");
+    }
+    out.push('\n');
+
+    let mut depth = 1;
+    for (idx, &op) in ir.tokens.iter().enumerate() {
+        if op == Op::LoopEnd {
+            depth -= 1;
+        }
+        out.extend(core::iter::repeat_n("    ", depth));
+        // Writing to a `String` never fails.
+        emit_op(&mut out, idx, op, options.eof_behavior).unwrap();
+        out.push('\n');
+        if op == Op::LoopStart {
+            depth += 1;
+        }
+    }
+
+    out.push_str("    return 0;\n}\n");
+    out
+}
+
+/// Write the C statement for an Op.
+fn emit_op(out: &mut String, idx: usize, op: Op, eof_behavior: EofBehavior) -> fmt::Result {
+    match op {
+        Op::IncPtr => emit_op(out, idx, Op::Move(1), eof_behavior),
+        Op::DecPtr => emit_op(out, idx, Op::Move(-1), eof_behavior),
+        Op::IncByte => emit_op(
+            out,
+            idx,
+            Op::Add {
+                offset: 0,
+                amount: 1,
+            },
+            eof_behavior,
+        ),
+        Op::DecByte => emit_op(
+            out,
+            idx,
+            Op::Add {
+                offset: 0,
+                amount: -1,
+            },
+            eof_behavior,
+        ),
+        Op::OutByte => emit_op(out, idx, Op::Out { offset: 0 }, eof_behavior),
+        Op::InByte => emit_op(out, idx, Op::In { offset: 0 }, eof_behavior),
+        Op::LoopStart => write!(out, "while (tape[p]) {{"),
+        Op::LoopEnd => write!(out, "}}"),
+        Op::Add { offset, amount } => {
+            // Unsigned arithmetic wraps, and truncates back to the cell.
+            let (op, amount) = split_sign(amount);
+            write!(out, "tape[{}] {op}= {amount}u;", cell(idx, offset))
+        }
+        Op::Move(amount) => write!(out, "p = at(p, {amount}, {idx});"),
+        Op::Set { offset, value } if value < 0 => {
+            write!(out, "tape[{}] = (cell)({value});", cell(idx, offset))
+        }
+        Op::Set { offset, value } => write!(out, "tape[{}] = {value};", cell(idx, offset)),
+        Op::MulAdd { offset, factor } => {
+            let (op, factor) = split_sign(factor);
+            write!(
+                out,
+                "if (tape[p]) tape[{}] {op}= tape[p] * {factor}u;",
+                cell(idx, offset)
+            )
+        }
+        Op::Scan { stride } => write!(out, "while (tape[p]) p = at(p, {stride}, {idx});"),
+        Op::Out { offset } => write!(out, "putchar((unsigned char)tape[{}]);", cell(idx, offset)),
+        Op::In { offset } => {
+            let cell = cell(idx, offset);
+            write!(
+                out,
+                "fflush(stdout); if ((c = getchar()) != EOF) tape[{cell}] = (cell)c;"
+            )?;
+            match eof_behavior {
+                EofBehavior::Unchanged => Ok(()),
+                EofBehavior::Zero => write!(out, " else tape[{cell}] = 0;"),
+                EofBehavior::MinusOne => write!(out, " else tape[{cell}] = (cell)-1;"),
+            }
+        }
+        Op::Debug => write!(out, "dump(p, {idx});"),
+    }
+}
+
+/// The index expression for the cell at `offset` from the pointer.
+fn cell(idx: usize, offset: i32) -> String {
+    match offset {
+        0 => "p".into(),
+        _ => format!("at(p, {offset}, {idx})"),
+    }
+}
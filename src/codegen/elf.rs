@@ -0,0 +1,460 @@
+// The ELF backend, writing x86-64 Linux executables without an assembler.
+
+use alloc::vec::Vec;
+
+use super::{
+    BUFFER_SIZE, CodegenOptions, DUMP_RADIUS,
+    asm::{Assembler, Cond, Label, Mem, R12, R13, R14, R15, RAX, RBX, RCX, RDI, RDX, RSI, Reg},
+    lower::{Hooks, lower},
+};
+use crate::{
+    cell::Cell,
+    ir::{IR, Op},
+    vm::EofBehavior,
+};
+
+/// Where the file, and so the code after the headers, is loaded.
+const CODE_ADDR: u32 = 0x40_0000;
+/// Where the zeroed data, ending with the tape, is mapped.
+const DATA_ADDR: u32 = 0x1000_0000;
+/// The size of the ELF header and the program headers before the code.
+const HEADERS_SIZE: u32 = 64 + 3 * 56;
+
+// The offsets of the variables and buffers from `DATA_ADDR`.
+const OUT_LEN: u32 = 0;
+const IN_POS: u32 = 8;
+const IN_LEN: u32 = 16;
+const ERR_LEN: u32 = 24;
+const NUM_BUFFER: u32 = 32;
+const NUM_BUFFER_SIZE: u32 = 24;
+const ERR_BUFFER: u32 = 64;
+const ERR_BUFFER_SIZE: u32 = 1024;
+const IN_BUFFER: u32 = ERR_BUFFER + ERR_BUFFER_SIZE;
+const OUT_BUFFER: u32 = IN_BUFFER + BUFFER_SIZE;
+const TAPE: u32 = OUT_BUFFER + BUFFER_SIZE;
+
+/// The address of a variable or buffer.
+const fn data(offset: u32) -> u32 {
+    DATA_ADDR + offset
+}
+
+/// Compile an IR to a static x86-64 Linux executable with a tape of `C`
+/// cells.
+///
+/// The program works like the one from [`x86_64::compile`], but is encoded
+/// directly, so no assembler or linker is needed.
+///
+/// [`x86_64::compile`]: super::x86_64::compile
+pub fn compile<C: Cell>(ir: &IR, options: &CodegenOptions) -> Vec<u8> {
+    let mut asm = Assembler::default();
+    let strings = Strings::new(&mut asm);
+    let routines = Routines {
+        exit: asm.label(),
+        flush: asm.label(),
+        put: asm.label(),
+        get: asm.label(),
+        err_str: asm.label(),
+        err_num: asm.label(),
+        err_flush: asm.label(),
+        tape_error: asm.label(),
+        dump: asm.label(),
+    };
+    let entry = asm.len() as u32;
+
+    // The tape's address is kept in r12, the pointer in rbx and the size of
+    // the tape in r13.
+    asm.mov_ri(R12, u64::from(data(TAPE)));
+    asm.xor(32, RBX, RBX);
+    asm.mov_ri(R13, u64::from(options.tape_size));
+
+    let mut runtime = Runtime {
+        routines,
+        eof_behavior: options.eof_behavior,
+        checks: Vec::new(),
+    };
+    lower::<C>(&mut asm, ir, &mut runtime);
+
+    asm.call(routines.flush);
+    asm.xor(32, RDI, RDI);
+    emit_runtime(&mut asm, &routines, &strings);
+    for (label, idx, reg) in runtime.checks {
+        asm.bind(label);
+        asm.mov(64, RSI, reg);
+        asm.mov_ri(RDI, idx as u64);
+        asm.jmp(routines.tape_error);
+    }
+    if ir.tokens.contains(&Op::Debug) {
+        emit_dump(&mut asm, &routines, &strings, C::BITS);
+    }
+
+    let data_size = u64::from(TAPE) + u64::from(options.tape_size) * u64::from(C::BITS / 8);
+    elf(&asm.finish(), entry, data_size)
+}
+
+/// Wrap code in an executable with the data mapped at `DATA_ADDR`.
+fn elf(code: &[u8], entry: u32, data_size: u64) -> Vec<u8> {
+    let file_size = u64::from(HEADERS_SIZE) + code.len() as u64;
+    let mut out = Vec::with_capacity(file_size as usize);
+
+    // The ELF header, for a little endian 64-bit executable.
+    out.extend_from_slice(b"\x7fELF\x02\x01\x01");
+    out.extend_from_slice(&[0; 9]);
+    out.extend_from_slice(&2u16.to_le_bytes());
+    // x86-64.
+    out.extend_from_slice(&62u16.to_le_bytes());
+    out.extend_from_slice(&1u32.to_le_bytes());
+    out.extend_from_slice(&u64::from(CODE_ADDR + HEADERS_SIZE + entry).to_le_bytes());
+    // The program headers follow, and there are no section headers.
+    out.extend_from_slice(&64u64.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    for half in [64u16, 56, 3, 64, 0, 0] {
+        out.extend_from_slice(&half.to_le_bytes());
+    }
+
+    // The whole file, readable and executable.
+    program_header(&mut out, 1, 0b101, CODE_ADDR, file_size, file_size);
+    // The data, readable and writable and all zeroes.
+    program_header(&mut out, 1, 0b110, DATA_ADDR, 0, data_size);
+    // A non-executable stack.
+    program_header(&mut out, 0x6474_e551, 0b110, 0, 0, 0);
+
+    out.extend_from_slice(code);
+    out
+}
+
+/// Write a program header for a segment at `addr` with `file_size` bytes
+/// from the start of the file.
+fn program_header(
+    out: &mut Vec<u8>,
+    kind: u32,
+    flags: u32,
+    addr: u32,
+    file_size: u64,
+    mem_size: u64,
+) {
+    out.extend_from_slice(&kind.to_le_bytes());
+    out.extend_from_slice(&flags.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out.extend_from_slice(&u64::from(addr).to_le_bytes());
+    out.extend_from_slice(&u64::from(addr).to_le_bytes());
+    out.extend_from_slice(&file_size.to_le_bytes());
+    out.extend_from_slice(&mem_size.to_le_bytes());
+    out.extend_from_slice(&0x1000u64.to_le_bytes());
+}
+
+/// The address and length of a string in the code.
+#[derive(Clone, Copy)]
+struct Str(u32, u32);
+
+/// The strings for error messages and dumps, placed before the code.
+struct Strings {
+    underflow: Str,
+    overflow: Str,
+    moved: Str,
+    minus: Str,
+    full_stop: Str,
+    dump_op: Str,
+    dump_ptr: Str,
+    dump_cells: Str,
+    dump_range: Str,
+    dump_colon: Str,
+    dump_space: Str,
+    dump_open: Str,
+    dump_close: Str,
+    newline: Str,
+}
+
+impl Strings {
+    fn new(asm: &mut Assembler) -> Self {
+        let mut string = |s: &str| {
+            let addr = CODE_ADDR + HEADERS_SIZE + asm.len() as u32;
+            asm.bytes(s.as_bytes());
+            Str(addr, s.len() as u32)
+        };
+        Self {
+            underflow: string("Error: tape underflow at op "),
+            overflow: string("Error: tape overflow at op "),
+            moved: string(": pointer moved to cell "),
+            minus: string("-"),
+            full_stop: string(".\n"),
+            dump_op: string("# at op "),
+            dump_ptr: string(": ptr "),
+            dump_cells: string(" | cells "),
+            dump_range: string("..="),
+            dump_colon: string(":"),
+            dump_space: string(" "),
+            dump_open: string(" ["),
+            dump_close: string("]"),
+            newline: string("\n"),
+        }
+    }
+}
+
+/// The routines the compiled code calls.
+#[derive(Clone, Copy)]
+struct Routines {
+    /// Exit with the status in edi.
+    exit: Label,
+    /// Write out the buffered output.
+    flush: Label,
+    /// Buffer the byte in al for output.
+    put: Label,
+    /// Read a byte of input into eax, or -1 at the end of it.
+    get: Label,
+    /// Append a string to the error message.
+    err_str: Label,
+    /// Append the number in rax to the error message.
+    err_num: Label,
+    /// Write the error message to stderr.
+    err_flush: Label,
+    /// Exit with an error for the op in edi moving the pointer to the cell
+    /// in rsi.
+    tape_error: Label,
+    /// Print the cells around the pointer for the op in edi.
+    dump: Label,
+}
+
+/// The code for Ops that calls the routines, and checks the tape.
+struct Runtime {
+    routines: Routines,
+    eof_behavior: EofBehavior,
+    /// The checks that cells are on the tape, with the index of their Op
+    /// and the register with the cell's index.
+    checks: Vec<(Label, usize, Reg)>,
+}
+
+impl Hooks for Runtime {
+    fn start_run(&mut self, _: &mut Assembler, _: usize, _: usize) {}
+
+    fn off_tape(&mut self, asm: &mut Assembler, idx: usize, reg: Reg) -> Label {
+        let label = asm.label();
+        self.checks.push((label, idx, reg));
+        label
+    }
+
+    fn output(&mut self, asm: &mut Assembler, _: usize, cell: Mem) {
+        asm.load(8, RAX, cell);
+        asm.call(self.routines.put);
+    }
+
+    fn input(&mut self, asm: &mut Assembler, _: usize, cell: Mem, width: u32) {
+        // The cell's address may be in rdx, which `get` overwrites.
+        let skip = asm.label();
+        asm.push(RDX);
+        asm.call(self.routines.get);
+        asm.pop(RDX);
+        match self.eof_behavior {
+            EofBehavior::Unchanged => {
+                asm.test(32, RAX, RAX);
+                asm.jcc(Cond::S, skip);
+            }
+            EofBehavior::Zero => {
+                asm.test(32, RAX, RAX);
+                asm.jcc(Cond::Ns, skip);
+                asm.xor(32, RAX, RAX);
+            }
+            EofBehavior::MinusOne if width == 64 => asm.cdqe(),
+            EofBehavior::MinusOne => {}
+        }
+        if self.eof_behavior != EofBehavior::Unchanged {
+            asm.bind(skip);
+        }
+        asm.mov(width, cell, RAX);
+        if self.eof_behavior == EofBehavior::Unchanged {
+            asm.bind(skip);
+        }
+    }
+
+    fn debug(&mut self, asm: &mut Assembler, idx: usize) {
+        asm.mov_ri(RDI, idx as u64);
+        asm.call(self.routines.dump);
+    }
+}
+
+/// Load a string's address and length into rsi and rdx, and append it to
+/// the error message.
+fn err_str(asm: &mut Assembler, routines: &Routines, Str(addr, len): Str) {
+    asm.mov_ri(RSI, u64::from(addr));
+    asm.mov_ri(RDX, u64::from(len));
+    asm.call(routines.err_str);
+}
+
+/// Write the routines other than `dump`, which starts with `exit`.
+fn emit_runtime(asm: &mut Assembler, routines: &Routines, strings: &Strings) {
+    asm.bind(routines.exit);
+    asm.mov_ri(RAX, 60);
+    asm.syscall();
+
+    // Write from rsi until rdx is 0, exiting if that fails.
+    let (write, done, failed) = (asm.label(), asm.label(), asm.label());
+    asm.bind(routines.flush);
+    asm.mov_ri(RSI, u64::from(data(OUT_BUFFER)));
+    asm.load(64, RDX, Mem::abs(data(OUT_LEN)));
+    asm.bind(write);
+    asm.test(64, RDX, RDX);
+    asm.jcc(Cond::E, done);
+    asm.mov_ri(RAX, 1);
+    asm.mov_ri(RDI, 1);
+    asm.syscall();
+    asm.test(64, RAX, RAX);
+    asm.jcc(Cond::Le, failed);
+    asm.add(64, RSI, RAX);
+    asm.sub(64, RDX, RAX);
+    asm.jmp(write);
+    asm.bind(done);
+    asm.mov_mi(64, Mem::abs(data(OUT_LEN)), 0);
+    asm.ret();
+    asm.bind(failed);
+    asm.mov_ri(RDI, 1);
+    asm.jmp(routines.exit);
+
+    asm.bind(routines.put);
+    asm.load(64, RCX, Mem::abs(data(OUT_LEN)));
+    asm.mov(8, Mem::base(RCX, data(OUT_BUFFER) as i32), RAX);
+    asm.add_i(64, RCX, 1);
+    asm.mov(64, Mem::abs(data(OUT_LEN)), RCX);
+    asm.cmp_i(64, RCX, BUFFER_SIZE as i32);
+    asm.jcc(Cond::E, routines.flush);
+    asm.ret();
+
+    // Flush the output before waiting for more input.
+    let (buffered, eof) = (asm.label(), asm.label());
+    asm.bind(routines.get);
+    asm.load(64, RCX, Mem::abs(data(IN_POS)));
+    asm.cmp(64, RCX, Mem::abs(data(IN_LEN)));
+    asm.jcc(Cond::B, buffered);
+    asm.call(routines.flush);
+    asm.xor(32, RAX, RAX);
+    asm.xor(32, RDI, RDI);
+    asm.mov_ri(RSI, u64::from(data(IN_BUFFER)));
+    asm.mov_ri(RDX, u64::from(BUFFER_SIZE));
+    asm.syscall();
+    asm.test(64, RAX, RAX);
+    asm.jcc(Cond::Le, eof);
+    asm.mov(64, Mem::abs(data(IN_LEN)), RAX);
+    asm.xor(32, RCX, RCX);
+    asm.bind(buffered);
+    asm.load_zx(8, RAX, Mem::base(RCX, data(IN_BUFFER) as i32));
+    asm.add_i(64, RCX, 1);
+    asm.mov(64, Mem::abs(data(IN_POS)), RCX);
+    asm.ret();
+    asm.bind(eof);
+    asm.mov_ri(RAX, u64::from(u32::MAX));
+    asm.ret();
+
+    // Append the rdx bytes at rsi.
+    asm.bind(routines.err_str);
+    asm.mov(64, RCX, RDX);
+    asm.load(64, RAX, Mem::abs(data(ERR_LEN)));
+    asm.lea(RDI, Mem::base(RAX, data(ERR_BUFFER) as i32));
+    asm.add(64, RAX, RCX);
+    asm.mov(64, Mem::abs(data(ERR_LEN)), RAX);
+    asm.rep_movsb();
+    asm.ret();
+
+    // Write the digits backwards from the end of the number buffer.
+    let digit = asm.label();
+    let end = data(NUM_BUFFER + NUM_BUFFER_SIZE);
+    asm.bind(routines.err_num);
+    asm.mov_ri(RSI, u64::from(end));
+    asm.mov_ri(RCX, 10);
+    asm.bind(digit);
+    asm.xor(32, RDX, RDX);
+    asm.div(RCX);
+    asm.add_i(32, RDX, i32::from(b'0'));
+    asm.sub_i(64, RSI, 1);
+    asm.mov(8, Mem::base(RSI, 0), RDX);
+    asm.test(64, RAX, RAX);
+    asm.jcc(Cond::Ne, digit);
+    asm.mov_ri(RDX, u64::from(end));
+    asm.sub(64, RDX, RSI);
+    asm.jmp(routines.err_str);
+
+    // Write the buffered output first.
+    asm.bind(routines.err_flush);
+    asm.call(routines.flush);
+    asm.mov_ri(RAX, 1);
+    asm.mov_ri(RDI, 2);
+    asm.mov_ri(RSI, u64::from(data(ERR_BUFFER)));
+    asm.load(64, RDX, Mem::abs(data(ERR_LEN)));
+    asm.syscall();
+    asm.mov_mi(64, Mem::abs(data(ERR_LEN)), 0);
+    asm.ret();
+
+    let (over, positive) = (asm.label(), asm.label());
+    asm.bind(routines.tape_error);
+    asm.mov(64, R14, RDI);
+    asm.mov(64, R15, RSI);
+    asm.test(64, R15, R15);
+    asm.jcc(Cond::Ns, over);
+    err_str(asm, routines, strings.underflow);
+    asm.jmp(positive);
+    asm.bind(over);
+    err_str(asm, routines, strings.overflow);
+    asm.bind(positive);
+    asm.mov(64, RAX, R14);
+    asm.call(routines.err_num);
+    err_str(asm, routines, strings.moved);
+    let unsigned = asm.label();
+    asm.test(64, R15, R15);
+    asm.jcc(Cond::Ns, unsigned);
+    err_str(asm, routines, strings.minus);
+    asm.neg(R15);
+    asm.bind(unsigned);
+    asm.mov(64, RAX, R15);
+    asm.call(routines.err_num);
+    err_str(asm, routines, strings.full_stop);
+    asm.call(routines.err_flush);
+    asm.mov_ri(RDI, 1);
+    asm.jmp(routines.exit);
+}
+
+/// Write the `dump` routine for a tape of `width` bit cells.
+fn emit_dump(asm: &mut Assembler, routines: &Routines, strings: &Strings, width: u32) {
+    asm.bind(routines.dump);
+    asm.mov(64, R14, RDI);
+    err_str(asm, routines, strings.dump_op);
+    asm.mov(64, RAX, R14);
+    asm.call(routines.err_num);
+    err_str(asm, routines, strings.dump_ptr);
+    asm.mov(64, RAX, RBX);
+    asm.call(routines.err_num);
+    err_str(asm, routines, strings.dump_cells);
+
+    // Cells r14..r15, clamped to the tape.
+    asm.lea(R15, Mem::base(RBX, DUMP_RADIUS as i32 + 1));
+    asm.cmp(64, R15, R13);
+    asm.cmov(Cond::A, R15, R13);
+    asm.xor(32, R14, R14);
+    asm.mov(64, RAX, RBX);
+    asm.sub_i(64, RAX, DUMP_RADIUS as i32);
+    asm.cmov(Cond::Ae, R14, RAX);
+    asm.mov(64, RAX, R14);
+    asm.call(routines.err_num);
+    err_str(asm, routines, strings.dump_range);
+    asm.lea(RAX, Mem::base(R15, -1));
+    asm.call(routines.err_num);
+    err_str(asm, routines, strings.dump_colon);
+
+    let (cell, other, unmarked, next) = (asm.label(), asm.label(), asm.label(), asm.label());
+    asm.bind(cell);
+    asm.cmp(64, R14, RBX);
+    asm.jcc(Cond::Ne, other);
+    err_str(asm, routines, strings.dump_open);
+    asm.jmp(unmarked);
+    asm.bind(other);
+    err_str(asm, routines, strings.dump_space);
+    asm.bind(unmarked);
+    asm.load_zx(width, RAX, Mem::indexed(R12, R14, (width / 8) as u8));
+    asm.call(routines.err_num);
+    asm.cmp(64, R14, RBX);
+    asm.jcc(Cond::Ne, next);
+    err_str(asm, routines, strings.dump_close);
+    asm.bind(next);
+    asm.add_i(64, R14, 1);
+    asm.cmp(64, R14, R15);
+    asm.jcc(Cond::B, cell);
+    err_str(asm, routines, strings.newline);
+    asm.jmp(routines.err_flush);
+}
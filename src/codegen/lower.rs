@@ -0,0 +1,192 @@
+// Lowering Ops to x86-64 machine code, shared by the ELF backend and the
+// JIT.
+
+use alloc::vec::Vec;
+
+use super::asm::{Assembler, Cond, Label, Mem, R12, R13, RAX, RBX, RDX, Reg};
+use crate::{
+    cell::Cell,
+    ir::{IR, Op},
+};
+
+/// What the code does where the backends differ.
+///
+/// The code keeps the tape's address in r12, the pointer in rbx and the
+/// size of the tape in r13, and uses rax and rdx as scratch registers.
+pub trait Hooks {
+    /// Write the code at the start of a straight run of Ops, from `idx` up
+    /// to `end`.
+    fn start_run(&mut self, asm: &mut Assembler, idx: usize, end: usize);
+
+    /// The label to jump to if the Op at `idx` moves to the cell in `reg`,
+    /// which is off the tape.
+    fn off_tape(&mut self, asm: &mut Assembler, idx: usize, reg: Reg) -> Label;
+
+    /// Write the code that outputs `cell` for the Op at `idx`.
+    fn output(&mut self, asm: &mut Assembler, idx: usize, cell: Mem);
+
+    /// Write the code that reads input into `cell`, of `width` bits, for
+    /// the Op at `idx`.
+    fn input(&mut self, asm: &mut Assembler, idx: usize, cell: Mem, width: u32);
+
+    /// Write the code for `#` at `idx`.
+    fn debug(&mut self, asm: &mut Assembler, idx: usize);
+}
+
+/// Write the code for an IR with a tape of `C` cells.
+///
+/// Returns the label of each Op and of the end of the program, which are
+/// only bound for Ops starting a straight run, since loops only jump to
+/// those, and for the end.
+pub fn lower<C: Cell>(asm: &mut Assembler, ir: &IR, hooks: &mut impl Hooks) -> Vec<Label> {
+    let labels: Vec<_> = (0..=ir.tokens.len()).map(|_| asm.label()).collect();
+    let mut lowering = Lowering {
+        asm,
+        hooks,
+        ir,
+        width: C::BITS,
+        labels: &labels,
+    };
+    for (idx, &op) in ir.tokens.iter().enumerate() {
+        if idx == 0 || starts_run(&ir.tokens, idx) {
+            let end = (idx + 1..ir.tokens.len())
+                .find(|&idx| starts_run(&ir.tokens, idx))
+                .unwrap_or(ir.tokens.len());
+            lowering.asm.bind(labels[idx]);
+            lowering.hooks.start_run(lowering.asm, idx, end);
+        }
+        lowering.emit_op(idx, op);
+    }
+    asm.bind(labels[ir.tokens.len()]);
+    labels
+}
+
+/// Whether an Op starts a straight run of Ops, because it's a loop or a
+/// `#`, or follows one.
+fn starts_run(tokens: &[Op], idx: usize) -> bool {
+    let boundary = |op: &Op| matches!(op, Op::LoopStart | Op::LoopEnd | Op::Debug);
+    tokens.get(idx).is_some_and(boundary)
+        || idx.checked_sub(1).is_some_and(|idx| boundary(&tokens[idx]))
+}
+
+/// Translates Ops to machine code.
+struct Lowering<'a, H> {
+    asm: &'a mut Assembler,
+    hooks: &'a mut H,
+    ir: &'a IR,
+    /// The width of a cell in bits.
+    width: u32,
+    /// The label of each Op, and of the end.
+    labels: &'a [Label],
+}
+
+impl<H: Hooks> Lowering<'_, H> {
+    fn emit_op(&mut self, idx: usize, op: Op) {
+        let width = self.width;
+        match op {
+            Op::IncPtr => self.emit_op(idx, Op::Move(1)),
+            Op::DecPtr => self.emit_op(idx, Op::Move(-1)),
+            Op::IncByte => self.emit_op(
+                idx,
+                Op::Add {
+                    offset: 0,
+                    amount: 1,
+                },
+            ),
+            Op::DecByte => self.emit_op(
+                idx,
+                Op::Add {
+                    offset: 0,
+                    amount: -1,
+                },
+            ),
+            Op::OutByte => self.emit_op(idx, Op::Out { offset: 0 }),
+            Op::InByte => self.emit_op(idx, Op::In { offset: 0 }),
+            Op::LoopStart => {
+                // Skip to after the matching `]`.
+                let end = self.ir.jump_table[idx] as usize;
+                self.asm.cmp_i(width, self.current(), 0);
+                self.asm.jcc(Cond::E, self.labels[end + 1]);
+            }
+            Op::LoopEnd => {
+                let start = self.ir.jump_table[idx] as usize;
+                self.asm.cmp_i(width, self.current(), 0);
+                self.asm.jcc(Cond::Ne, self.labels[start + 1]);
+            }
+            // The low bits of an amount are the same however it's
+            // truncated, and 64-bit immediates are sign extended.
+            Op::Add { offset, amount } => {
+                let cell = self.cell(idx, offset);
+                self.asm.add_i(width, cell, amount);
+            }
+            Op::Move(amount) => self.move_ptr(idx, amount),
+            Op::Set { offset, value } => {
+                let cell = self.cell(idx, offset);
+                self.asm.mov_mi(width, cell, value);
+            }
+            Op::MulAdd { offset, factor } => {
+                let skip = self.asm.label();
+                self.asm.load_zx(width, RAX, self.current());
+                self.asm.test(64, RAX, RAX);
+                self.asm.jcc(Cond::E, skip);
+                let cell = self.cell(idx, offset);
+                self.asm.imul_i(RAX, RAX, factor);
+                self.asm.add(width, cell, RAX);
+                self.asm.bind(skip);
+            }
+            Op::Scan { stride } => {
+                let (step, test) = (self.asm.label(), self.asm.label());
+                self.asm.jmp(test);
+                self.asm.bind(step);
+                self.move_ptr(idx, stride);
+                self.asm.bind(test);
+                self.asm.cmp_i(width, self.current(), 0);
+                self.asm.jcc(Cond::Ne, step);
+            }
+            Op::Out { offset } => {
+                let cell = self.cell(idx, offset);
+                self.hooks.output(self.asm, idx, cell);
+            }
+            Op::In { offset } => {
+                let cell = self.cell(idx, offset);
+                self.hooks.input(self.asm, idx, cell, width);
+            }
+            Op::Debug => self.hooks.debug(self.asm, idx),
+        }
+    }
+
+    /// Move the pointer by `amount` cells, which leaves it where it was if
+    /// that's off the tape.
+    fn move_ptr(&mut self, idx: usize, amount: i32) {
+        self.asm.lea(RDX, Mem::base(RBX, amount));
+        self.check(idx, RDX);
+        self.asm.mov(64, RBX, RDX);
+    }
+
+    /// The current cell.
+    fn current(&self) -> Mem {
+        Mem::indexed(R12, RBX, (self.width / 8) as u8)
+    }
+
+    /// The cell at `offset` from the pointer, first checking it's on the
+    /// tape if it isn't the current cell.
+    fn cell(&mut self, idx: usize, offset: i32) -> Mem {
+        if offset == 0 {
+            return self.current();
+        }
+        self.asm.lea(RDX, Mem::base(RBX, offset));
+        self.check(idx, RDX);
+        Mem::indexed(R12, RDX, (self.width / 8) as u8)
+    }
+
+    /// Jump to the hook's label if the cell in `reg` is off the tape.
+    ///
+    /// The pointer is an index into the tape, so a single unsigned
+    /// comparison against the tape size catches both ends: cells before the
+    /// tape are huge when unsigned.
+    fn check(&mut self, idx: usize, reg: Reg) {
+        let label = self.hooks.off_tape(self.asm, idx, reg);
+        self.asm.cmp(64, reg, R13);
+        self.asm.jcc(Cond::Ae, label);
+    }
+}
@@ -0,0 +1,47 @@
+// Code generators that translate the IR into other languages.
+
+pub(crate) mod asm;
+pub mod c;
+pub mod elf;
+pub(crate) mod lower;
+pub mod rust;
+pub mod x86_64;
+
+use crate::vm::EofBehavior;
+
+/// How many cells on either side of the pointer the generated code prints
+/// for [`Op::Debug`](crate::ir::Op::Debug).
+const DUMP_RADIUS: u32 = 8;
+
+/// How many bytes of input and output the native backends buffer.
+const BUFFER_SIZE: u32 = 4096;
+
+/// Options for the code generators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodegenOptions {
+    /// The size of the tape in cells. Moving off either end of it stops
+    /// the program with an error, like [`TapePolicy::Error`].
+    ///
+    /// [`TapePolicy::Error`]: crate::vm::TapePolicy::Error
+    pub tape_size: u32,
+    /// What the `,` instruction does at the end of the input.
+    pub eof_behavior: EofBehavior,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        Self {
+            tape_size: 30_000,
+            eof_behavior: EofBehavior::default(),
+        }
+    }
+}
+
+/// Split an amount into the operator to apply it with and its magnitude.
+fn split_sign(amount: i32) -> (char, u32) {
+    if amount < 0 {
+        ('-', amount.unsigned_abs())
+    } else {
+        ('+', amount as u32)
+    }
+}
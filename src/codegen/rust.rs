@@ -0,0 +1,261 @@
+// The Rust backend.
+
+use core::fmt::{self, Write};
+
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+
+use super::{CodegenOptions, DUMP_RADIUS, split_sign};
+use crate::{
+    cell::Cell,
+    ir::{IR, Op},
+    vm::EofBehavior,
+};
+
+/// The start of the `run` function, `{tape_size}` and `{cell}` are filled
+/// in.
+const PRELUDE: &str = r#"/// Run the program, reading its input from `input` and writing its output
+/// to `output`. Generated by bfc.
+#[allow(unused)]
+pub fn run(
+    input: &mut impl std::io::Read,
+    output: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    const TAPE_SIZE: usize = {tape_size};
+
+    type Cell = {cell};
+
+    /// The index of the cell `offset` cells away from `ptr`, or an error
+    /// if that's off the tape.
+    fn at(ptr: usize, offset: i64, op: u32) -> std::io::Result<usize> {
+        let idx = ptr as i64 + offset;
+        if (0..TAPE_SIZE as i64).contains(&idx) {
+            return Ok(idx as usize);
+        }
+        let end = if idx < 0 { "underflow" } else { "overflow" };
+        Err(std::io::Error::other(format!(
+            "tape {end} at op {op}: pointer moved to cell {idx}"
+        )))
+    }
+"#;
+
+/// Reads a byte for `,`, only included if the program uses it.
+const READ_BYTE: &str = r#"
+    /// The next byte of input, or `None` at the end of it.
+    fn read_byte(input: &mut impl std::io::Read) -> std::io::Result<Option<u8>> {
+        std::io::Read::bytes(input).next().transpose()
+    }
+"#;
+
+/// Prints the cells around the pointer for `#`, only included if the
+/// program uses it. `{radius}` is filled in.
+const DUMP: &str = r##"
+    /// Print the cells around the pointer to stderr.
+    fn dump(tape: &[Cell], ptr: usize, op: u32) {
+        let start = ptr.saturating_sub({radius});
+        let end = (ptr + {radius} + 1).min(TAPE_SIZE);
+        eprint!("# at op {op}: ptr {ptr} | cells {start}..={}:", end - 1);
+        for (idx, cell) in tape.iter().enumerate().take(end).skip(start) {
+            if idx == ptr {
+                eprint!(" [{cell}]");
+            } else {
+                eprint!(" {cell}");
+            }
+        }
+        eprintln!();
+    }
+"##;
+
+/// Runs the program on stdin and stdout, exiting with an error message if
+/// it fails.
+const MAIN: &str = r#"
+fn main() -> std::process::ExitCode {
+    let mut output = std::io::BufWriter::new(std::io::stdout().lock());
+    let result = run(&mut std::io::stdin().lock(), &mut output);
+    // Get the output out before any error message.
+    drop(output);
+    match result {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}.");
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+"#;
+
+/// Compile an IR to a standalone Rust program with a tape of `C` cells,
+/// which runs the program on stdin and stdout.
+///
+/// The program is the function from [`compile_fn`] and a `main` that calls
+/// it.
+pub fn compile<C: Cell>(ir: &IR, options: &CodegenOptions) -> String {
+    let mut out = String::from("// Generated by bfc.\n\n");
+    out.push_str(&compile_fn::<C>(ir, options));
+    out.push_str(MAIN);
+    out
+}
+
+/// Compile an IR to a Rust function with a tape of `C` cells, for
+/// embedding the program in other code, e.g. with `include!` from a build
+/// script's output:
+///
+/// ```text
+/// pub fn run(
+///     input: &mut impl std::io::Read,
+///     output: &mut impl std::io::Write,
+/// ) -> std::io::Result<()>
+/// ```
+///
+/// Output is truncated to bytes, and moving off the tape returns an error.
+/// The function flushes `output` before reading input and when it returns,
+/// but may write it a byte at a time, so it's best buffered.
+pub fn compile_fn<C: Cell>(ir: &IR, options: &CodegenOptions) -> String {
+    let mut out = String::new();
+    out.push_str(
+        &PRELUDE
+            .replace("{tape_size}", &options.tape_size.to_string())
+            .replace("{cell}", &format!("u{}", C::BITS)),
+    );
+    if ir
+        .tokens
+        .iter()
+        .any(|op| matches!(op, Op::InByte | Op::In { .. }))
+    {
+        out.push_str(READ_BYTE);
+    }
+    if ir.tokens.contains(&Op::Debug) {
+        out.push_str(&DUMP.replace("{radius}", &DUMP_RADIUS.to_string()));
+    }
+
+    out.push_str("\n    let mut tape = vec![0 as Cell; TAPE_SIZE];\n");
+    out.push_str("    let mut p = 0;\n\n");
+
+    let mut depth = 1;
+    for (idx, &op) in ir.tokens.iter().enumerate() {
+        if op == Op::LoopEnd {
+            depth -= 1;
+        }
+        out.extend(core::iter::repeat_n("    ", depth));
+        // Writing to a `String` never fails.
+        emit_op::<C>(&mut out, idx, op, options.eof_behavior).unwrap();
+        out.push('\n');
+        if op == Op::LoopStart {
+            depth += 1;
+        }
+    }
+
+    out.push_str("\n    output.flush()\n}\n");
+    out
+}
+
+/// Write the Rust statement for an Op.
+fn emit_op<C: Cell>(
+    out: &mut String,
+    idx: usize,
+    op: Op,
+    eof_behavior: EofBehavior,
+) -> fmt::Result {
+    match op {
+        Op::IncPtr => emit_op::<C>(out, idx, Op::Move(1), eof_behavior),
+        Op::DecPtr => emit_op::<C>(out, idx, Op::Move(-1), eof_behavior),
+        Op::IncByte => emit_op::<C>(
+            out,
+            idx,
+            Op::Add {
+                offset: 0,
+                amount: 1,
+            },
+            eof_behavior,
+        ),
+        Op::DecByte => emit_op::<C>(
+            out,
+            idx,
+            Op::Add {
+                offset: 0,
+                amount: -1,
+            },
+            eof_behavior,
+        ),
+        Op::OutByte => emit_op::<C>(out, idx, Op::Out { offset: 0 }, eof_behavior),
+        Op::InByte => emit_op::<C>(out, idx, Op::In { offset: 0 }, eof_behavior),
+        Op::LoopStart => write!(out, "while tape[p] != 0 {{"),
+        Op::LoopEnd => write!(out, "}}"),
+        Op::Add { offset, amount } => {
+            let (method, amount) = wrapping::<C>(amount);
+            update(out, idx, offset, &format!("{method}({amount})"))
+        }
+        Op::Move(amount) => write!(out, "p = at(p, {amount}, {idx})?;"),
+        Op::Set { offset, value } => {
+            let value = C::from_i32(value).to_u64();
+            write!(out, "tape[{}] = {value};", cell(idx, offset))
+        }
+        Op::MulAdd { offset, factor } => {
+            let (method, factor) = wrapping::<C>(factor);
+            write!(
+                out,
+                "if tape[p] != 0 {{ let x = tape[p].wrapping_mul({factor}); "
+            )?;
+            update(out, idx, offset, &format!("{method}(x)"))?;
+            write!(out, " }}")
+        }
+        Op::Scan { stride } => write!(out, "while tape[p] != 0 {{ p = at(p, {stride}, {idx})?; }}"),
+        Op::Out { offset } => write!(
+            out,
+            "output.write_all(&[tape[{}] as u8])?;",
+            cell(idx, offset)
+        ),
+        Op::In { offset } => {
+            let cell = cell(idx, offset);
+            write!(out, "output.flush()?; ")?;
+            match eof_behavior {
+                EofBehavior::Unchanged => write!(
+                    out,
+                    "if let Some(byte) = read_byte(input)? {{ tape[{cell}] = Cell::from(byte); }}"
+                ),
+                EofBehavior::Zero => write!(
+                    out,
+                    "tape[{cell}] = read_byte(input)?.map_or(0, Cell::from);"
+                ),
+                EofBehavior::MinusOne => write!(
+                    out,
+                    "tape[{cell}] = read_byte(input)?.map_or(Cell::MAX, Cell::from);"
+                ),
+            }
+        }
+        Op::Debug => write!(out, "output.flush()?; dump(&tape, p, {idx});"),
+    }
+}
+
+/// Write a statement that replaces the cell at `offset` from the pointer
+/// with the result of calling `method` on it.
+fn update(out: &mut String, idx: usize, offset: i32, method: &str) -> fmt::Result {
+    match offset {
+        0 => write!(out, "tape[p] = tape[p].{method};"),
+        _ => write!(
+            out,
+            "{{ let c = &mut tape[at(p, {offset}, {idx})?]; *c = c.{method}; }}"
+        ),
+    }
+}
+
+/// The index expression for the cell at `offset` from the pointer.
+fn cell(idx: usize, offset: i32) -> String {
+    match offset {
+        0 => "p".into(),
+        _ => format!("at(p, {offset}, {idx})?"),
+    }
+}
+
+/// The wrapping method to apply an amount to a `C` cell with, and its
+/// magnitude truncated to the cell.
+fn wrapping<C: Cell>(amount: i32) -> (&'static str, u64) {
+    let (op, amount) = split_sign(amount);
+    let method = match op {
+        '-' => "wrapping_sub",
+        _ => "wrapping_add",
+    };
+    (method, u64::from(amount) & (u64::MAX >> (64 - C::BITS)))
+}
@@ -0,0 +1,540 @@
+// The x86-64 backend.
+//
+// This lowers Ops like `lower`, which the ELF backend and the JIT share, but
+// writes GNU assembly text so the output can be read and assembled with the
+// system toolchain. `lower` drives the machine code assembler directly, which
+// has no notion of the named labels and `.equ` constants the text relies on,
+// so the two copies are kept in step by hand: they use the same registers and,
+// I/O aside, the same instructions for each Op, and a change to one belongs in
+// the other. The only difference is that moves here update rbx in place, since
+// moving off the tape ends the program, where `lower` keeps the old pointer
+// for the JIT to resume from. The CLI tests check both against `bfc run`.
+
+use core::fmt::{self, Write};
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use super::{BUFFER_SIZE, CodegenOptions, DUMP_RADIUS, split_sign};
+use crate::{
+    cell::Cell,
+    ir::{IR, Op},
+    vm::EofBehavior,
+};
+
+/// The part of the program before the compiled code. `{tape_size}`,
+/// `{cell_bytes}` and `{buffer_size}` are filled in.
+///
+/// The generated code keeps the tape's address in r12, the pointer in rbx
+/// and the size of the tape in r13.
+const PRELUDE: &str = r#"# Generated by bfc.
+    .intel_syntax noprefix
+
+    .equ TAPE_SIZE, {tape_size}
+    .equ CELL_BYTES, {cell_bytes}
+    .equ BUFFER_SIZE, {buffer_size}
+
+    .bss
+    .balign 64
+tape:
+    .skip TAPE_SIZE * CELL_BYTES
+in_buffer:
+    .skip BUFFER_SIZE
+out_buffer:
+    .skip BUFFER_SIZE
+err_buffer:
+    .skip 1024
+num_buffer:
+    .skip 24
+in_pos:
+    .skip 8
+in_len:
+    .skip 8
+out_len:
+    .skip 8
+err_len:
+    .skip 8
+
+    .section .rodata
+underflow:
+    .ascii "Error: tape underflow at op "
+    .equ UNDERFLOW_LEN, . - underflow
+overflow:
+    .ascii "Error: tape overflow at op "
+    .equ OVERFLOW_LEN, . - overflow
+moved:
+    .ascii ": pointer moved to cell "
+    .equ MOVED_LEN, . - moved
+minus:
+    .ascii "-"
+full_stop:
+    .ascii ".\n"
+
+    .text
+    .globl _start
+_start:
+    lea r12, [rip + tape]
+    xor ebx, ebx
+    mov r13, TAPE_SIZE
+
+"#;
+
+/// The end of the compiled code and the routines it calls.
+const RUNTIME: &str = r#"
+    call flush
+    xor edi, edi
+exit:
+    mov eax, 60
+    syscall
+
+# Write out the buffered output, exiting if that fails.
+flush:
+    lea rsi, [rip + out_buffer]
+    mov rdx, [rip + out_len]
+1:
+    test rdx, rdx
+    jz 2f
+    mov eax, 1
+    mov edi, 1
+    syscall
+    test rax, rax
+    jle 3f
+    add rsi, rax
+    sub rdx, rax
+    jmp 1b
+2:
+    mov qword ptr [rip + out_len], 0
+    ret
+3:
+    mov edi, 1
+    jmp exit
+
+# Buffer the byte in al for output.
+put:
+    mov rcx, [rip + out_len]
+    lea rdx, [rip + out_buffer]
+    mov [rdx + rcx], al
+    inc rcx
+    mov [rip + out_len], rcx
+    cmp rcx, BUFFER_SIZE
+    je flush
+    ret
+
+# Read the next byte of input into eax, or -1 at the end of it. The output
+# is flushed before waiting for more input.
+get:
+    mov rcx, [rip + in_pos]
+    cmp rcx, [rip + in_len]
+    jb 1f
+    call flush
+    xor eax, eax
+    xor edi, edi
+    lea rsi, [rip + in_buffer]
+    mov edx, BUFFER_SIZE
+    syscall
+    test rax, rax
+    jle 2f
+    mov [rip + in_len], rax
+    xor ecx, ecx
+1:
+    lea rdx, [rip + in_buffer]
+    movzx eax, byte ptr [rdx + rcx]
+    inc rcx
+    mov [rip + in_pos], rcx
+    ret
+2:
+    mov eax, -1
+    ret
+
+# Append the rdx bytes at rsi to the error message.
+err_str:
+    mov rcx, rdx
+    mov rax, [rip + err_len]
+    lea rdi, [rip + err_buffer]
+    add rdi, rax
+    add rax, rcx
+    mov [rip + err_len], rax
+    rep movsb
+    ret
+
+# Append the unsigned number in rax to the error message.
+err_num:
+    lea rsi, [rip + num_buffer + 24]
+    mov ecx, 10
+1:
+    xor edx, edx
+    div rcx
+    add dl, '0'
+    dec rsi
+    mov [rsi], dl
+    test rax, rax
+    jnz 1b
+    lea rdx, [rip + num_buffer + 24]
+    sub rdx, rsi
+    jmp err_str
+
+# Write the error message to stderr, after the buffered output.
+err_flush:
+    call flush
+    mov eax, 1
+    mov edi, 2
+    lea rsi, [rip + err_buffer]
+    mov rdx, [rip + err_len]
+    syscall
+    mov qword ptr [rip + err_len], 0
+    ret
+
+# Exit with an error for the op in edi moving the pointer to the cell in
+# rsi, off the tape.
+tape_error:
+    mov r14d, edi
+    mov r15, rsi
+    lea rsi, [rip + overflow]
+    mov edx, OVERFLOW_LEN
+    test r15, r15
+    jns 1f
+    lea rsi, [rip + underflow]
+    mov edx, UNDERFLOW_LEN
+1:
+    call err_str
+    mov eax, r14d
+    call err_num
+    lea rsi, [rip + moved]
+    mov edx, MOVED_LEN
+    call err_str
+    test r15, r15
+    jns 2f
+    lea rsi, [rip + minus]
+    mov edx, 1
+    call err_str
+    neg r15
+2:
+    mov rax, r15
+    call err_num
+    lea rsi, [rip + full_stop]
+    mov edx, 2
+    call err_str
+    call err_flush
+    mov edi, 1
+    jmp exit
+"#;
+
+/// Prints the cells around the pointer for `#`, only included if the
+/// program uses it. `{radius}` and `{load}` are filled in.
+const DUMP: &str = r##"
+    .section .rodata
+dump_op:
+    .ascii "# at op "
+dump_ptr:
+    .ascii ": ptr "
+dump_cells:
+    .ascii " | cells "
+dump_range:
+    .ascii "..="
+dump_colon:
+    .ascii ":"
+dump_space:
+    .ascii " ["
+dump_close:
+    .ascii "]\n"
+
+    .text
+# Print the cells around the pointer to stderr, for the op in edi.
+dump:
+    mov r14d, edi
+    lea rsi, [rip + dump_op]
+    mov edx, 8
+    call err_str
+    mov eax, r14d
+    call err_num
+    lea rsi, [rip + dump_ptr]
+    mov edx, 6
+    call err_str
+    mov rax, rbx
+    call err_num
+    lea rsi, [rip + dump_cells]
+    mov edx, 9
+    call err_str
+    # Cells start..end, clamped to the tape.
+    lea r15, [rbx + {radius} + 1]
+    cmp r15, r13
+    cmova r15, r13
+    xor r14d, r14d
+    mov rax, rbx
+    sub rax, {radius}
+    cmovae r14, rax
+    mov rax, r14
+    call err_num
+    lea rsi, [rip + dump_range]
+    mov edx, 3
+    call err_str
+    lea rax, [r15 - 1]
+    call err_num
+    lea rsi, [rip + dump_colon]
+    mov edx, 1
+    call err_str
+1:
+    lea rsi, [rip + dump_space]
+    mov edx, 1
+    cmp r14, rbx
+    jne 2f
+    mov edx, 2
+2:
+    call err_str
+    {load} [r12 + r14 * CELL_BYTES]
+    call err_num
+    cmp r14, rbx
+    jne 3f
+    lea rsi, [rip + dump_close]
+    mov edx, 1
+    call err_str
+3:
+    inc r14
+    cmp r14, r15
+    jb 1b
+    lea rsi, [rip + dump_close + 1]
+    mov edx, 1
+    call err_str
+    jmp err_flush
+"##;
+
+/// Compile an IR to x86-64 assembly for Linux with a tape of `C` cells, in
+/// GNU as syntax.
+///
+/// The program uses syscalls for I/O without linking to libc, so it can be
+/// built into a static binary with `as -o program.o program.s` and
+/// `ld -o program program.o`. Output is buffered and truncated to bytes,
+/// and moving off the tape exits with an error.
+pub fn compile<C: Cell>(ir: &IR, options: &CodegenOptions) -> String {
+    let mut out = PRELUDE
+        .replace("{tape_size}", &options.tape_size.to_string())
+        .replace("{cell_bytes}", &(C::BITS / 8).to_string())
+        .replace("{buffer_size}", &BUFFER_SIZE.to_string());
+
+    // The ops that check a cell is on the tape, and the register with the
+    // cell's index.
+    let mut checks = Vec::new();
+    for (idx, &op) in ir.tokens.iter().enumerate() {
+        // Writing to a `String` never fails.
+        emit_op::<C>(&mut out, ir, idx, op, options.eof_behavior, &mut checks).unwrap();
+    }
+    out.push_str(RUNTIME);
+    for (idx, register) in checks {
+        write!(
+            out,
+            "\n.Lbound{idx}:\n    mov rsi, {register}\n    mov edi, {idx}\n    jmp tape_error\n"
+        )
+        .unwrap();
+    }
+
+    if ir.tokens.contains(&Op::Debug) {
+        out.push_str(
+            &DUMP
+                .replace("{radius}", &DUMP_RADIUS.to_string())
+                .replace("{load}", load::<C>()),
+        );
+    }
+    out
+}
+
+/// Write the instructions for an Op, adding the checks it jumps to to
+/// `checks`.
+fn emit_op<C: Cell>(
+    out: &mut String,
+    ir: &IR,
+    idx: usize,
+    op: Op,
+    eof_behavior: EofBehavior,
+    checks: &mut Vec<(usize, &'static str)>,
+) -> fmt::Result {
+    let size = size::<C>();
+    let acc = accumulator::<C>();
+    match op {
+        Op::IncPtr => emit_op::<C>(out, ir, idx, Op::Move(1), eof_behavior, checks),
+        Op::DecPtr => emit_op::<C>(out, ir, idx, Op::Move(-1), eof_behavior, checks),
+        Op::IncByte => emit_op::<C>(
+            out,
+            ir,
+            idx,
+            Op::Add {
+                offset: 0,
+                amount: 1,
+            },
+            eof_behavior,
+            checks,
+        ),
+        Op::DecByte => emit_op::<C>(
+            out,
+            ir,
+            idx,
+            Op::Add {
+                offset: 0,
+                amount: -1,
+            },
+            eof_behavior,
+            checks,
+        ),
+        Op::OutByte => emit_op::<C>(out, ir, idx, Op::Out { offset: 0 }, eof_behavior, checks),
+        Op::InByte => emit_op::<C>(out, ir, idx, Op::In { offset: 0 }, eof_behavior, checks),
+        Op::LoopStart => {
+            writeln!(out, "    cmp {size} [r12 + rbx * CELL_BYTES], 0")?;
+            writeln!(out, "    je .Lexit{idx}")?;
+            writeln!(out, ".Lloop{idx}:")
+        }
+        Op::LoopEnd => {
+            let start = ir.jump_table[idx];
+            writeln!(out, "    cmp {size} [r12 + rbx * CELL_BYTES], 0")?;
+            writeln!(out, "    jne .Lloop{start}")?;
+            writeln!(out, ".Lexit{start}:")
+        }
+        Op::Add { offset, amount } => {
+            let cell = cell(out, idx, offset, checks)?;
+            let (op, amount) = immediate::<C>(amount);
+            writeln!(out, "    {op} {size} {cell}, {amount}")
+        }
+        Op::Move(amount) => {
+            writeln!(out, "    add rbx, {amount}")?;
+            check(out, idx, "rbx", checks)
+        }
+        Op::Set { offset, value } => {
+            let cell = cell(out, idx, offset, checks)?;
+            writeln!(out, "    mov {size} {cell}, {}", value_of::<C>(value))
+        }
+        Op::MulAdd { offset, factor } => {
+            writeln!(out, "    {} [r12 + rbx * CELL_BYTES]", load::<C>())?;
+            writeln!(out, "    test rax, rax")?;
+            writeln!(out, "    jz .Lskip{idx}")?;
+            let cell = cell(out, idx, offset, checks)?;
+            writeln!(out, "    imul rax, rax, {factor}")?;
+            writeln!(out, "    add {size} {cell}, {acc}")?;
+            writeln!(out, ".Lskip{idx}:")
+        }
+        Op::Scan { stride } => {
+            writeln!(out, "    jmp .Lscan{idx}")?;
+            writeln!(out, ".Lmove{idx}:")?;
+            writeln!(out, "    add rbx, {stride}")?;
+            check(out, idx, "rbx", checks)?;
+            writeln!(out, ".Lscan{idx}:")?;
+            writeln!(out, "    cmp {size} [r12 + rbx * CELL_BYTES], 0")?;
+            writeln!(out, "    jne .Lmove{idx}")
+        }
+        Op::Out { offset } => {
+            let cell = cell(out, idx, offset, checks)?;
+            writeln!(out, "    mov al, byte ptr {cell}")?;
+            writeln!(out, "    call put")
+        }
+        Op::In { offset } => {
+            writeln!(out, "    call get")?;
+            match eof_behavior {
+                EofBehavior::Unchanged => {
+                    writeln!(out, "    test eax, eax")?;
+                    writeln!(out, "    js .Lskip{idx}")?;
+                }
+                EofBehavior::Zero => {
+                    writeln!(out, "    test eax, eax")?;
+                    writeln!(out, "    jns .Lread{idx}")?;
+                    writeln!(out, "    xor eax, eax")?;
+                    writeln!(out, ".Lread{idx}:")?;
+                }
+                EofBehavior::MinusOne if C::BITS == 64 => writeln!(out, "    cdqe")?,
+                EofBehavior::MinusOne => {}
+            }
+            let cell = cell(out, idx, offset, checks)?;
+            writeln!(out, "    mov {size} {cell}, {acc}")?;
+            if eof_behavior == EofBehavior::Unchanged {
+                writeln!(out, ".Lskip{idx}:")?;
+            }
+            Ok(())
+        }
+        Op::Debug => {
+            writeln!(out, "    mov edi, {idx}")?;
+            writeln!(out, "    call dump")
+        }
+    }
+}
+
+/// Get the operand for the cell at `offset` from the pointer, first
+/// writing the instructions to check it's on the tape if it isn't the
+/// current cell.
+fn cell(
+    out: &mut String,
+    idx: usize,
+    offset: i32,
+    checks: &mut Vec<(usize, &'static str)>,
+) -> Result<&'static str, fmt::Error> {
+    if offset == 0 {
+        return Ok("[r12 + rbx * CELL_BYTES]");
+    }
+    writeln!(out, "    lea rdx, [rbx + {offset}]")?;
+    check(out, idx, "rdx", checks)?;
+    Ok("[r12 + rdx * CELL_BYTES]")
+}
+
+/// Write the instructions to exit with an error if the cell in `register`
+/// is off the tape.
+fn check(
+    out: &mut String,
+    idx: usize,
+    register: &'static str,
+    checks: &mut Vec<(usize, &'static str)>,
+) -> fmt::Result {
+    writeln!(out, "    cmp {register}, r13")?;
+    writeln!(out, "    jae .Lbound{idx}")?;
+    checks.push((idx, register));
+    Ok(())
+}
+
+/// The instruction to add an amount to a `C` cell with, and its immediate
+/// operand.
+fn immediate<C: Cell>(amount: i32) -> (&'static str, String) {
+    let (op, magnitude) = split_sign(amount);
+    let magnitude = u64::from(magnitude) & (u64::MAX >> (64 - C::BITS));
+    match op {
+        // Immediates are sign extended to 64 bits, so `-i32::MIN` doesn't
+        // fit.
+        _ if magnitude > i32::MAX as u64 && C::BITS == 64 => ("add", amount.to_string()),
+        '-' => ("sub", magnitude.to_string()),
+        _ => ("add", magnitude.to_string()),
+    }
+}
+
+/// The immediate operand that sets a `C` cell to `value`.
+fn value_of<C: Cell>(value: i32) -> String {
+    match C::BITS {
+        // Sign extended to the cell.
+        64 => value.to_string(),
+        _ => C::from_i32(value).to_u64().to_string(),
+    }
+}
+
+/// The size of a `C` memory operand.
+fn size<C: Cell>() -> &'static str {
+    match C::BITS {
+        8 => "byte ptr",
+        16 => "word ptr",
+        32 => "dword ptr",
+        _ => "qword ptr",
+    }
+}
+
+/// The part of rax that holds a `C` cell.
+fn accumulator<C: Cell>() -> &'static str {
+    match C::BITS {
+        8 => "al",
+        16 => "ax",
+        32 => "eax",
+        _ => "rax",
+    }
+}
+
+/// The instruction that loads a `C` cell into rax, zero extending it.
+fn load<C: Cell>() -> &'static str {
+    match C::BITS {
+        8 => "movzx eax, byte ptr",
+        16 => "movzx eax, word ptr",
+        32 => "mov eax, dword ptr",
+        _ => "mov rax, qword ptr",
+    }
+}
@@ -0,0 +1,208 @@
+// A step debugger on top of the VM.
+
+use alloc::vec::Vec;
+
+use crate::{
+    cell::Cell,
+    io::{Input, Output},
+    ir::Op,
+    vm::{RunStatus, RuntimeError, VM},
+};
+
+/// How a [`Condition`] compares the cell to its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A condition on the value of a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    /// The cell to check, relative to the starting cell, or the cell
+    /// under the pointer if `None`.
+    pub cell: Option<i64>,
+    /// How to compare the cell to `value`.
+    pub comparison: Comparison,
+    /// The value to compare the cell to.
+    pub value: u64,
+}
+
+impl Condition {
+    /// Check the condition against the VM's tape.
+    pub fn holds<I: Input, O: Output, C: Cell>(&self, vm: &VM<I, O, C>) -> bool {
+        let cell = vm.cell(self.cell.unwrap_or_else(|| vm.pointer())).to_u64();
+        match self.comparison {
+            Comparison::Eq => cell == self.value,
+            Comparison::Ne => cell != self.value,
+            Comparison::Lt => cell < self.value,
+            Comparison::Le => cell <= self.value,
+            Comparison::Gt => cell > self.value,
+            Comparison::Ge => cell >= self.value,
+        }
+    }
+}
+
+/// Stop before executing an Op.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    /// The index of the Op to stop at.
+    pub op_idx: u32,
+    /// Only stop if this holds when the Op is reached.
+    pub condition: Option<Condition>,
+}
+
+/// Why the debugger stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop<C> {
+    /// The requested step or loop finished.
+    Done,
+    /// The VM reached the breakpoint at this index in
+    /// [`Debugger::breakpoints`].
+    Breakpoint(usize),
+    /// A watched cell changed.
+    Watchpoint {
+        /// The cell, relative to the starting cell.
+        cell: i64,
+        /// The value before the change.
+        old: C,
+        /// The value after the change.
+        new: C,
+    },
+    /// The program has finished executing.
+    Halted,
+    /// The program is waiting at a `,` for input that isn't available yet.
+    AwaitingInput,
+}
+
+/// A debugger that runs a [`VM`] until it hits a breakpoint or a watched
+/// cell changes.
+pub struct Debugger<I, O, C: Cell = u8> {
+    /// The VM being debugged.
+    vm: VM<I, O, C>,
+    /// Where to stop.
+    pub breakpoints: Vec<Breakpoint>,
+    /// The cells, relative to the starting cell, to stop at when they
+    /// change.
+    pub watchpoints: Vec<i64>,
+    /// The values of the watched cells before the current step, kept to
+    /// reuse its allocation.
+    watched: Vec<C>,
+}
+
+impl<I: Input, O: Output, C: Cell> Debugger<I, O, C> {
+    /// Create a debugger for a VM, without breakpoints or watchpoints.
+    pub fn new(vm: VM<I, O, C>) -> Self {
+        Self {
+            vm,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            watched: Vec::new(),
+        }
+    }
+
+    /// Get a reference to the VM.
+    pub fn vm(&self) -> &VM<I, O, C> {
+        &self.vm
+    }
+
+    /// Get a mutable reference to the VM.
+    pub fn vm_mut(&mut self) -> &mut VM<I, O, C> {
+        &mut self.vm
+    }
+
+    /// Consume the debugger, returning the VM.
+    pub fn into_vm(self) -> VM<I, O, C> {
+        self.vm
+    }
+
+    /// Execute a single Op.
+    pub fn step(&mut self) -> Result<Stop<C>, RuntimeError> {
+        self.run_until(|_| true)
+    }
+
+    /// Run until a breakpoint or watchpoint is hit, or the program stops.
+    pub fn resume(&mut self) -> Result<Stop<C>, RuntimeError> {
+        self.run_until(|_| false)
+    }
+
+    /// Run a whole loop if the VM is at its start, and execute a single Op
+    /// otherwise.
+    pub fn step_over(&mut self) -> Result<Stop<C>, RuntimeError> {
+        let idx = self.vm.current_token_idx();
+        match self.vm.ir().tokens.get(idx as usize) {
+            Some(Op::LoopStart) => {
+                let end = self.vm.ir().jump_table[idx as usize];
+                self.run_until(|idx| idx == end + 1)
+            }
+            _ => self.step(),
+        }
+    }
+
+    /// Run until the innermost loop the VM is in has finished, or until the
+    /// program stops if it isn't in a loop.
+    pub fn finish_loop(&mut self) -> Result<Stop<C>, RuntimeError> {
+        let idx = self.vm.current_token_idx() as usize;
+        let ir = self.vm.ir();
+        // The innermost loop starts at the closest `[` before the current
+        // Op whose matching `]` isn't before it.
+        let end = ir.tokens[..idx.min(ir.tokens.len())]
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|&(start, &op)| op == Op::LoopStart && ir.jump_table[start] as usize >= idx)
+            .map(|(start, _)| ir.jump_table[start]);
+
+        match end {
+            Some(end) => self.run_until(|idx| idx == end + 1),
+            None => self.resume(),
+        }
+    }
+
+    /// Step until `done` returns true for the index of the next Op, stopping
+    /// early at breakpoints and watchpoints.
+    ///
+    /// At least one Op is executed, so breakpoints at the current Op don't
+    /// stop the VM again.
+    fn run_until(&mut self, mut done: impl FnMut(u32) -> bool) -> Result<Stop<C>, RuntimeError> {
+        loop {
+            self.watched.clear();
+            if !self.watchpoints.is_empty() {
+                let cells = self.watchpoints.iter().map(|&cell| self.vm.cell(cell));
+                self.watched.extend(cells);
+            }
+
+            let status = self.vm.run_for(1)?;
+
+            for (&cell, &old) in self.watchpoints.iter().zip(&self.watched) {
+                let new = self.vm.cell(cell);
+                if new != old {
+                    return Ok(Stop::Watchpoint { cell, old, new });
+                }
+            }
+            match status {
+                RunStatus::Halted => return Ok(Stop::Halted),
+                RunStatus::AwaitingInput => return Ok(Stop::AwaitingInput),
+                RunStatus::OutOfFuel => {}
+            }
+
+            let idx = self.vm.current_token_idx();
+            if done(idx) {
+                return Ok(Stop::Done);
+            }
+            let hit = self.breakpoints.iter().position(|breakpoint| {
+                breakpoint.op_idx == idx
+                    && breakpoint
+                        .condition
+                        .is_none_or(|condition| condition.holds(&self.vm))
+            });
+            if let Some(hit) = hit {
+                return Ok(Stop::Breakpoint(hit));
+            }
+        }
+    }
+}
@@ -0,0 +1,365 @@
+// An interactive front end for the step debugger.
+
+use std::{
+    io::{self, BufRead, Write},
+    process::ExitCode,
+};
+
+use bfc::{
+    Cell, Debugger, IR, OutputEncoding, RuntimeError, VM, VMOptions,
+    debug::{Breakpoint, Comparison, Condition, Stop},
+};
+
+use crate::{
+    cli::Args,
+    dump_to_stderr,
+    repl::{LineInput, drain_output, read_input, show_tape},
+};
+
+/// The help text printed by the `help` command.
+const COMMANDS: &str = "\
+Commands:
+  s, step [N]              Execute N ops [default: 1]
+  n, next                  Step over the loop starting at the current op
+  f, finish                Run until the current loop has finished
+  c, continue              Run until a breakpoint or watchpoint is hit
+  b, break <LOC> [if <COND>]
+                           Stop at LOC, an op index or LINE:COLUMN in the
+                           source, if COND holds, e.g. `if @3 == 0` for
+                           cell 3 or `if > 10` for the current cell
+  w, watch <CELL>          Stop when CELL changes
+  d, delete [N]            Delete breakpoint N, or all breakpoints and
+                           watchpoints
+  i, info                  List breakpoints and watchpoints
+  p, print [CELL]          Print CELL, or the cells around the pointer
+  l, list                  Show the current op in the source
+  q, quit                  Quit the debugger
+  h, help                  Print this help
+An empty line repeats the last command.
+";
+
+/// A command that runs the debugged program.
+type Run<C> = fn(&mut Debugger<LineInput, Vec<u8>, C>) -> Result<Stop<C>, RuntimeError>;
+
+/// Debug the program on stdin and stdout with a tape of `C` cells, giving
+/// it `input` if there is any instead of asking for it.
+pub fn debug<C: Cell>(
+    ir: IR,
+    source: &str,
+    input: Option<&str>,
+    args: &Args,
+) -> io::Result<ExitCode> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+
+    let (memory_buffer_size, tape_policy) = args.tape();
+    let options = VMOptions {
+        memory_buffer_size,
+        tape_policy,
+        output_encoding: OutputEncoding::Truncate,
+        eof_behavior: args.eof_behavior,
+        output: Vec::new(),
+        input: input.map_or_else(LineInput::default, LineInput::ended),
+        jit: false,
+    };
+    let mut vm = VM::<_, _, C>::from_ir(ir, options);
+    dump_to_stderr(&mut vm);
+    let mut debugger = Debugger::new(vm);
+
+    show_location(debugger.vm(), source, &mut stdout)?;
+    let mut last_command = String::new();
+    loop {
+        write!(stdout, "(bfc) ")?;
+        stdout.flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            writeln!(stdout)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        if line.trim().is_empty() {
+            line = last_command.clone();
+        } else {
+            last_command = line.clone();
+        }
+
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        let words: Vec<_> = words.collect();
+
+        let run: Run<C> = match command {
+            "s" | "step" => {
+                let count = match words.first().map(|count| count.parse()) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        writeln!(stdout, "Invalid step count `{}`.", words[0])?;
+                        continue;
+                    }
+                };
+                let mut at_line_start = true;
+                let mut stop = Ok(Stop::Done);
+                for _ in 0..count {
+                    stop = step(&mut debugger, Debugger::step, &mut stdin, &mut stdout)?;
+                    drain_output(debugger.vm_mut(), &mut stdout, &mut at_line_start)?;
+                    if stop != Ok(Stop::Done) {
+                        break;
+                    }
+                }
+                report(&debugger, stop, source, at_line_start, &mut stdout)?;
+                continue;
+            }
+            "n" | "next" => Debugger::step_over,
+            "f" | "finish" => Debugger::finish_loop,
+            "c" | "continue" => Debugger::resume,
+            "b" | "break" => {
+                match parse_breakpoint(&words, debugger.vm().ir(), source) {
+                    Ok(breakpoint) => {
+                        writeln!(
+                            stdout,
+                            "Breakpoint {} at op {}.",
+                            debugger.breakpoints.len(),
+                            breakpoint.op_idx
+                        )?;
+                        debugger.breakpoints.push(breakpoint);
+                    }
+                    Err(err) => writeln!(stdout, "{err}")?,
+                }
+                continue;
+            }
+            "w" | "watch" => {
+                match words.first().and_then(|cell| cell.parse().ok()) {
+                    Some(cell) => {
+                        writeln!(stdout, "Watching cell {cell}.")?;
+                        debugger.watchpoints.push(cell);
+                    }
+                    None => writeln!(stdout, "Expected a cell to watch.")?,
+                }
+                continue;
+            }
+            "d" | "delete" => {
+                match words.first().map(|idx| idx.parse::<usize>()) {
+                    None => {
+                        debugger.breakpoints.clear();
+                        debugger.watchpoints.clear();
+                    }
+                    Some(Ok(idx)) if idx < debugger.breakpoints.len() => {
+                        debugger.breakpoints.remove(idx);
+                    }
+                    Some(_) => writeln!(stdout, "No breakpoint `{}`.", words[0])?,
+                }
+                continue;
+            }
+            "i" | "info" => {
+                for (idx, breakpoint) in debugger.breakpoints.iter().enumerate() {
+                    write!(stdout, "Breakpoint {idx} at op {}", breakpoint.op_idx)?;
+                    if let Some(condition) = breakpoint.condition {
+                        write!(stdout, " if {}", format_condition(condition))?;
+                    }
+                    writeln!(stdout)?;
+                }
+                for cell in &debugger.watchpoints {
+                    writeln!(stdout, "Watching cell {cell}")?;
+                }
+                continue;
+            }
+            "p" | "print" => {
+                match words.first().map(|cell| cell.parse::<i64>()) {
+                    None => show_tape(debugger.vm(), &mut stdout)?,
+                    Some(Ok(cell)) => {
+                        writeln!(stdout, "cell {cell}: {}", debugger.vm().cell(cell).to_u64())?
+                    }
+                    Some(Err(_)) => writeln!(stdout, "Invalid cell `{}`.", words[0])?,
+                }
+                continue;
+            }
+            "l" | "list" => {
+                show_location(debugger.vm(), source, &mut stdout)?;
+                continue;
+            }
+            "q" | "quit" => return Ok(ExitCode::SUCCESS),
+            "h" | "help" => {
+                write!(stdout, "{COMMANDS}")?;
+                continue;
+            }
+            _ => {
+                writeln!(stdout, "Unknown command `{command}`, try `help`.")?;
+                continue;
+            }
+        };
+
+        let stop = step(&mut debugger, run, &mut stdin, &mut stdout)?;
+        let mut at_line_start = true;
+        drain_output(debugger.vm_mut(), &mut stdout, &mut at_line_start)?;
+        report(&debugger, stop, source, at_line_start, &mut stdout)?;
+    }
+}
+
+/// Run a debugger command, reading a line of input for the program
+/// whenever it waits for some.
+fn step<C: Cell>(
+    debugger: &mut Debugger<LineInput, Vec<u8>, C>,
+    run: Run<C>,
+    stdin: &mut impl BufRead,
+    stdout: &mut impl Write,
+) -> io::Result<Result<Stop<C>, RuntimeError>> {
+    loop {
+        match run(debugger) {
+            Ok(Stop::AwaitingInput) => {
+                let mut at_line_start = true;
+                drain_output(debugger.vm_mut(), stdout, &mut at_line_start)?;
+                write!(stdout, "{}input> ", if at_line_start { "" } else { "\n" })?;
+                stdout.flush()?;
+                read_input(debugger.vm_mut(), stdin)?;
+            }
+            stop => return Ok(stop),
+        }
+    }
+}
+
+/// Report why the debugger stopped and where.
+fn report<C: Cell>(
+    debugger: &Debugger<LineInput, Vec<u8>, C>,
+    stop: Result<Stop<C>, RuntimeError>,
+    source: &str,
+    at_line_start: bool,
+    stdout: &mut impl Write,
+) -> io::Result<()> {
+    // Keep the report off the end of the program's output.
+    if !at_line_start {
+        writeln!(stdout)?;
+    }
+
+    match stop {
+        Ok(Stop::Done | Stop::AwaitingInput) => {}
+        Ok(Stop::Breakpoint(idx)) => writeln!(stdout, "Hit breakpoint {idx}.")?,
+        Ok(Stop::Watchpoint { cell, old, new }) => writeln!(
+            stdout,
+            "Cell {cell} changed from {} to {}.",
+            old.to_u64(),
+            new.to_u64()
+        )?,
+        Ok(Stop::Halted) => {
+            writeln!(stdout, "The program has finished.")?;
+            return show_tape(debugger.vm(), stdout);
+        }
+        Err(err) => writeln!(stdout, "Error: {err}.")?,
+    }
+
+    show_location(debugger.vm(), source, stdout)?;
+    show_tape(debugger.vm(), stdout)
+}
+
+/// Show the next op to execute and where it is in the source.
+fn show_location<C: Cell>(
+    vm: &VM<LineInput, Vec<u8>, C>,
+    source: &str,
+    stdout: &mut impl Write,
+) -> io::Result<()> {
+    let idx = vm.current_token_idx() as usize;
+    let (Some(op), Some(span)) = (vm.ir().tokens.get(idx), vm.ir().spans.get(idx)) else {
+        return writeln!(stdout, "At the end of the program.");
+    };
+    let (line, column) = span.locate(source);
+    writeln!(stdout, "op {idx} at {line}:{column}: {op:?}")?;
+    writeln!(stdout, "{}", span.underline(source))
+}
+
+/// Parse the arguments of a `break` command.
+fn parse_breakpoint(words: &[&str], ir: &IR, source: &str) -> Result<Breakpoint, String> {
+    let Some(&location) = words.first() else {
+        return Err("Expected an op index or LINE:COLUMN to break at.".into());
+    };
+
+    let op_idx = match location.split_once(':') {
+        Some((line, column)) => {
+            let pos = line
+                .parse()
+                .ok()
+                .zip(column.parse().ok())
+                .and_then(|(line, column)| offset_of(source, line, column))
+                .ok_or_else(|| format!("Invalid source position `{location}`."))?;
+            ir.op_at(pos)
+                .ok_or_else(|| format!("No op at or after `{location}`."))?
+        }
+        None => location
+            .parse()
+            .ok()
+            .filter(|&idx| idx < ir.tokens.len())
+            .ok_or_else(|| format!("Invalid op index `{location}`."))?,
+    };
+
+    let condition = match &words[1..] {
+        [] => None,
+        ["if", condition @ ..] => Some(parse_condition(condition)?),
+        _ => return Err("Expected `if` before the condition.".into()),
+    };
+
+    Ok(Breakpoint {
+        op_idx: op_idx as u32,
+        condition,
+    })
+}
+
+/// Parse a condition like `@3 == 0`, or `> 10` for the current cell.
+fn parse_condition(words: &[&str]) -> Result<Condition, String> {
+    let (cell, words) = match words {
+        [cell, rest @ ..] if cell.starts_with('@') => {
+            let cell = cell[1..]
+                .parse()
+                .map_err(|_| format!("Invalid cell `{cell}`."))?;
+            (Some(cell), rest)
+        }
+        _ => (None, words),
+    };
+    let [comparison, value] = words else {
+        return Err("Expected a condition like `@3 == 0` or `> 10`.".into());
+    };
+
+    let comparison = match *comparison {
+        "==" => Comparison::Eq,
+        "!=" => Comparison::Ne,
+        "<" => Comparison::Lt,
+        "<=" => Comparison::Le,
+        ">" => Comparison::Gt,
+        ">=" => Comparison::Ge,
+        _ => return Err(format!("Unknown comparison `{comparison}`.")),
+    };
+    let value = value
+        .parse()
+        .map_err(|_| format!("Invalid value `{value}`."))?;
+
+    Ok(Condition {
+        cell,
+        comparison,
+        value,
+    })
+}
+
+/// Format a condition the way `break` takes it.
+fn format_condition(condition: Condition) -> String {
+    let comparison = match condition.comparison {
+        Comparison::Eq => "==",
+        Comparison::Ne => "!=",
+        Comparison::Lt => "<",
+        Comparison::Le => "<=",
+        Comparison::Gt => ">",
+        Comparison::Ge => ">=",
+    };
+    match condition.cell {
+        Some(cell) => format!("@{cell} {comparison} {}", condition.value),
+        None => format!("{comparison} {}", condition.value),
+    }
+}
+
+/// The byte offset of a 1-based line and column in `source`.
+fn offset_of(source: &str, line: usize, column: usize) -> Option<usize> {
+    let line_start = match line.checked_sub(1)? {
+        0 => 0,
+        line => source.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let text = source[line_start..].split('\n').next()?;
+    let (offset, _) = text.char_indices().nth(column.checked_sub(1)?)?;
+    Some(line_start + offset)
+}
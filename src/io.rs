@@ -0,0 +1,146 @@
+// Input and output for the VM.
+
+use core::task::Poll;
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+/// A source of bytes for the `,` instruction.
+pub trait Input {
+    /// Read a byte, returning `Ready(None)` at the end of the input and
+    /// `Pending` if no input is available yet.
+    fn read(&mut self) -> Poll<Option<u8>>;
+}
+
+/// A sink for the bytes of the `.` instruction.
+pub trait Output {
+    /// Write a byte.
+    fn write(&mut self, byte: u8);
+
+    /// Flush any buffered bytes, called before every read from the input
+    /// so prompts show up before the program waits on them.
+    fn flush(&mut self) {}
+}
+
+impl<F: FnMut() -> Poll<Option<u8>>> Input for F {
+    #[inline]
+    fn read(&mut self) -> Poll<Option<u8>> {
+        self()
+    }
+}
+
+impl<F: FnMut(u8)> Output for F {
+    #[inline]
+    fn write(&mut self, byte: u8) {
+        self(byte)
+    }
+}
+
+/// Reads the slice from the front, ending the input once it's empty.
+impl Input for &[u8] {
+    fn read(&mut self) -> Poll<Option<u8>> {
+        let Some((&byte, rest)) = self.split_first() else {
+            return Poll::Ready(None);
+        };
+        *self = rest;
+        Poll::Ready(Some(byte))
+    }
+}
+
+/// Reads the queue from the front, waiting for more input once it's
+/// empty, so it can be refilled between runs.
+impl Input for VecDeque<u8> {
+    fn read(&mut self) -> Poll<Option<u8>> {
+        match self.pop_front() {
+            Some(byte) => Poll::Ready(Some(byte)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Output for Vec<u8> {
+    #[inline]
+    fn write(&mut self, byte: u8) {
+        self.push(byte);
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::std_io::{ReadInput, WriteOutput};
+
+#[cfg(feature = "std")]
+mod std_io {
+    use core::task::Poll;
+
+    use std::io::{self, ErrorKind, Read, Write};
+
+    use super::{Input, Output};
+
+    /// An [`Input`] reading from a [`std::io::Read`].
+    ///
+    /// Read errors end the input, except for [`ErrorKind::WouldBlock`]
+    /// which means no input is available yet.
+    pub struct ReadInput<R>(pub R);
+
+    impl<R: Read> Input for ReadInput<R> {
+        fn read(&mut self) -> Poll<Option<u8>> {
+            let mut buffer = [0; 1];
+            loop {
+                return match self.0.read(&mut buffer) {
+                    Ok(0) => Poll::Ready(None),
+                    Ok(_) => Poll::Ready(Some(buffer[0])),
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => Poll::Pending,
+                    Err(_) => Poll::Ready(None),
+                };
+            }
+        }
+    }
+
+    /// An [`Output`] writing to a [`std::io::Write`].
+    ///
+    /// Bytes written after an error are dropped, the error is returned by
+    /// [`WriteOutput::finish`].
+    pub struct WriteOutput<W> {
+        writer: W,
+        error: Option<io::Error>,
+    }
+
+    impl<W: Write> WriteOutput<W> {
+        /// Wrap a writer.
+        pub fn new(writer: W) -> Self {
+            Self {
+                writer,
+                error: None,
+            }
+        }
+
+        /// Whether writing has failed, any further output is dropped.
+        pub fn failed(&self) -> bool {
+            self.error.is_some()
+        }
+
+        /// Flush the writer and return it, or the first error that
+        /// occurred.
+        pub fn finish(mut self) -> io::Result<W> {
+            if let Some(err) = self.error.take() {
+                return Err(err);
+            }
+            self.writer.flush()?;
+            Ok(self.writer)
+        }
+    }
+
+    impl<W: Write> Output for WriteOutput<W> {
+        fn write(&mut self, byte: u8) {
+            if self.error.is_none() {
+                self.error = self.writer.write_all(&[byte]).err();
+            }
+        }
+
+        fn flush(&mut self) {
+            if self.error.is_none() {
+                self.error = self.writer.flush().err();
+            }
+        }
+    }
+}
@@ -1,11 +1,12 @@
 // Brainfuck IR/Parser.
 
-use core::{fmt, str::FromStr};
+use core::{fmt, ops::Range, str::FromStr};
 
 use alloc::{boxed::Box, vec::Vec};
 
 /// Represents a single Brainfuck instruction/operation.
-// size = 3 bits, physical size = 1 byte
+// The first eight variants map 1:1 onto source characters, the rest
+// are only ever produced by the optimization passes.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Op {
@@ -17,6 +18,39 @@ pub enum Op {
     InByte,
     LoopStart,
     LoopEnd,
+    /// Add a (wrapping) amount to the cell at `offset` from the pointer.
+    Add {
+        offset: i32,
+        amount: i32,
+    },
+    /// Move the cell pointer by a relative amount.
+    Move(i32),
+    /// Set the cell at `offset` from the pointer to a value.
+    Set {
+        offset: i32,
+        value: i32,
+    },
+    /// Add the current cell multiplied by `factor` to the cell at
+    /// `offset` from it.
+    MulAdd {
+        offset: i32,
+        factor: i32,
+    },
+    /// Move the cell pointer by `stride` until it lands on a zero cell.
+    Scan {
+        stride: i32,
+    },
+    /// Output the cell at `offset` from the pointer.
+    Out {
+        offset: i32,
+    },
+    /// Read input into the cell at `offset` from the pointer.
+    In {
+        offset: i32,
+    },
+    /// Hand a snapshot of the tape to the VM's debug hook, only parsed
+    /// from `#` with [`Dialect::debug_dump`].
+    Debug,
 }
 
 impl Op {
@@ -38,8 +72,10 @@ impl Op {
     }
 
     /// Convert a token back into a character.
-    pub fn into_char(self) -> char {
-        match self {
+    ///
+    /// Returns `None` for ops that have no single-character form.
+    pub fn into_char(self) -> Option<char> {
+        let ch = match self {
             Self::IncPtr => '>',
             Self::DecPtr => '<',
             Self::IncByte => '+',
@@ -48,12 +84,49 @@ impl Op {
             Self::InByte => ',',
             Self::LoopStart => '[',
             Self::LoopEnd => ']',
+            Self::Debug => '#',
+            _ => return None,
+        };
+
+        Some(ch)
+    }
+
+    /// The amount this op adds to the current cell, if it only does that.
+    fn add_amount(self) -> Option<i32> {
+        match self.add_at() {
+            Some((0, amount)) => Some(amount),
+            _ => None,
+        }
+    }
+
+    /// The `(offset, amount)` this op adds to a cell, if it only does that.
+    fn add_at(self) -> Option<(i32, i32)> {
+        match self {
+            Self::IncByte => Some((0, 1)),
+            Self::DecByte => Some((0, -1)),
+            Self::Add { offset, amount } => Some((offset, amount)),
+            _ => None,
+        }
+    }
+
+    /// Whether this op ends a basic block.
+    fn ends_block(self) -> bool {
+        matches!(self, Self::LoopStart | Self::LoopEnd | Self::Scan { .. })
+    }
+
+    /// The amount this op moves the cell pointer by, if it only does that.
+    fn move_amount(self) -> Option<i32> {
+        match self {
+            Self::IncPtr => Some(1),
+            Self::DecPtr => Some(-1),
+            Self::Move(amount) => Some(amount),
+            _ => None,
         }
     }
 }
 
 /// The intermediate representation for a Brainfuck program.
-// just a list of [Op]s and a jump table.
+// just a list of [Op]s, a jump table and the source span of each Op.
 //
 // `jump_table[i]` will give the Op idx to conditionally
 // jump to for the `ops[i]` Op, only two types of
@@ -61,9 +134,12 @@ impl Op {
 //
 // jump_table[loop_start_idx] = loop_end_idx
 // jump_table[loop_end_idx] = loop_start_idx
+#[derive(Debug, Clone)]
 pub struct IR {
     pub tokens: Box<[Op]>,
     pub jump_table: Box<[u32]>,
+    /// The source each Op was made from, parallel to `tokens`.
+    pub spans: Box<[Span]>,
 }
 
 /// Kinds of parse errors that can occur.
@@ -75,83 +151,772 @@ pub enum ParseErrorKind {
     UnexpectedLoopEnd,
 }
 
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnclosedLoop => f.write_str("unclosed loop, this `[` has no matching `]`"),
+            Self::UnexpectedLoopEnd => f.write_str("unexpected `]`, there is no matching `[`"),
+        }
+    }
+}
+
+/// A range of bytes in the source string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// The byte offset of the start of the range.
+    pub start: u32,
+    /// The byte offset just past the end of the range.
+    pub end: u32,
+}
+
+impl Span {
+    /// The 1-based line and column of the start of the span in `source`.
+    pub fn locate(self, source: &str) -> (u32, u32) {
+        locate(source, self.start as usize)
+    }
+
+    /// Show the line of `source` the span starts on, with the span
+    /// underlined.
+    pub fn underline(self, source: &str) -> Underline<'_> {
+        Underline { span: self, source }
+    }
+
+    /// The smallest span covering both spans.
+    fn join(self, other: Self) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
 /// A parsing error with position and kind.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ParseError {
-    /// The index of the token where the error occurred.
-    pub token_pos: u32,
+    /// The bytes of the character where the error occurred.
+    pub span: Span,
+    /// The 1-based line where the error occurred.
+    pub line: u32,
+    /// The 1-based column, in characters, where the error occurred.
+    pub column: u32,
     /// The type of error that occurred.
     pub kind: ParseErrorKind,
 }
 
 impl ParseError {
-    /// Create a new parse error.
-    #[inline]
-    pub const fn new(token_pos: u32, kind: ParseErrorKind) -> Self {
-        Self { token_pos, kind }
+    /// Create a new parse error for the character at byte offset `pos`
+    /// in `source`.
+    pub fn new(source: &str, pos: usize, kind: ParseErrorKind) -> Self {
+        let ch_len = source[pos..].chars().next().map_or(0, char::len_utf8);
+        let (line, column) = locate(source, pos);
+
+        Self {
+            span: Span {
+                start: pos as u32,
+                end: (pos + ch_len) as u32,
+            },
+            line,
+            column,
+            kind,
+        }
+    }
+
+    /// Render the error together with the offending source line and a
+    /// caret pointing at the error, `source` must be the parsed string.
+    pub fn render<'a>(&'a self, source: &'a str) -> Report<'a> {
+        Report::new(&self.kind, self.span, source)
     }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "parse error at {}: {:?}", self.token_pos, self.kind)
+        write!(
+            f,
+            "parse error at {}:{}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+/// A place where a missing bracket probably belongs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suggestion {
+    /// The byte offset to insert the bracket at.
+    pub pos: u32,
+    /// The 1-based line to insert the bracket at.
+    pub line: u32,
+    /// The 1-based column, in characters, to insert the bracket at.
+    pub column: u32,
+}
+
+impl Suggestion {
+    /// Create a new suggestion for byte offset `pos` in `source`.
+    pub fn new(source: &str, pos: usize) -> Self {
+        let (line, column) = locate(source, pos);
+        Self {
+            pos: pos as u32,
+            line,
+            column,
+        }
+    }
+}
+
+/// A parse error found by [`IR::parse_all`], with a guess at how to fix it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The error that was found.
+    pub error: ParseError,
+    /// Where the missing bracket probably belongs, going by indentation.
+    pub suggestion: Option<Suggestion>,
+}
+
+impl Diagnostic {
+    /// Render the diagnostic like [`ParseError::render`], followed by the
+    /// suggested fix.
+    pub fn render<'a>(&'a self, source: &'a str) -> Report<'a> {
+        let bracket = match self.error.kind {
+            ParseErrorKind::UnclosedLoop => ']',
+            ParseErrorKind::UnexpectedLoopEnd => '[',
+        };
+        Report {
+            suggestion: self
+                .suggestion
+                .as_ref()
+                .map(|suggestion| (bracket, suggestion)),
+            ..self.error.render(source)
+        }
+    }
+}
+
+/// An error rendered against its source, see [`ParseError::render`].
+pub struct Report<'a> {
+    /// What went wrong.
+    message: &'a dyn fmt::Display,
+    /// The offending source.
+    span: Span,
+    /// The missing bracket and where it probably belongs.
+    suggestion: Option<(char, &'a Suggestion)>,
+    source: &'a str,
+}
+
+impl<'a> Report<'a> {
+    /// Report `message` about `span` in `source`.
+    pub(crate) fn new(message: &'a dyn fmt::Display, span: Span, source: &'a str) -> Self {
+        Self {
+            message,
+            span,
+            suggestion: None,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, column) = self.span.locate(self.source);
+        let gutter = gutter_width(line);
+
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(f, "{:gutter$}--> {line}:{column}", "")?;
+        write!(f, "{}", self.span.underline(self.source))?;
+
+        if let Some((bracket, suggestion)) = self.suggestion {
+            write!(
+                f,
+                "\n{:gutter$} = help: the matching `{bracket}` probably belongs at {}:{}",
+                "", suggestion.line, suggestion.column
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A line of source with a span underlined, see [`Span::underline`].
+pub struct Underline<'a> {
+    span: Span,
+    source: &'a str,
+}
+
+impl fmt::Display for Underline<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pos = self.span.start as usize;
+        let line = line_at(self.source, pos);
+        let line_start = line.as_ptr() as usize - self.source.as_ptr() as usize;
+        let (line_number, _) = locate(self.source, pos);
+        let gutter = gutter_width(line_number);
+        // Only the part of the span on its first line is underlined.
+        let width = self.source[pos..self.span.end as usize]
+            .lines()
+            .next()
+            .map_or(1, |text| text.chars().count().max(1));
+
+        writeln!(f, "{:gutter$} |", "")?;
+        writeln!(f, "{line_number} | {line}")?;
+        write!(f, "{:gutter$} | ", "")?;
+        // Keep tabs so the caret lines up with the offending character.
+        for ch in self.source[line_start..pos].chars() {
+            f.write_str(if ch == '\t' { "\t" } else { " " })?;
+        }
+        for _ in 0..width {
+            f.write_str("^")?;
+        }
+        Ok(())
     }
 }
 
+/// The width of the line number gutter for a snippet of line `line`.
+fn gutter_width(line: u32) -> usize {
+    line.ilog10() as usize + 1
+}
+
 impl FromStr for IR {
     type Err = ParseError;
 
     /// Parse a Brainfuck source string into an IR.
+    ///
+    /// Reports the first unexpected `]` or, failing that, the innermost
+    /// unclosed `[`. Use [`IR::parse_all`] to get every error.
     fn from_str(input: &str) -> Result<Self, ParseError> {
-        let mut tokens = Vec::new();
-        let mut jump_table = Vec::new();
-        let mut loop_starts = Vec::new();
-
-        for (token_pos, char) in input.char_indices() {
-            // Skip characters that are not Brainfuck instructions.
-            let Some(token) = Op::from_char(char) else {
-                continue;
-            };
-
-            match token {
-                Op::LoopStart => {
-                    // Record the position of the `[`.
-                    loop_starts.push((tokens.len(), token_pos as u32));
+        Dialect::default().parse(input)
+    }
+}
+
+impl IR {
+    /// Parse a Brainfuck source string into an IR, collecting every
+    /// unexpected `]` and unclosed `[` instead of stopping at the first.
+    ///
+    /// The diagnostics are sorted by their position in the source.
+    pub fn parse_all(input: &str) -> Result<Self, Vec<Diagnostic>> {
+        Dialect::default().parse_all(input)
+    }
+}
+
+/// Opt-in extensions to the Brainfuck language, all disabled by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dialect {
+    /// Parse `#` as [`Op::Debug`] instead of skipping it as a comment.
+    pub debug_dump: bool,
+    /// Treat the first `!` as the end of the code, with the rest of the
+    /// source being the program's input.
+    pub input_separator: bool,
+}
+
+impl Dialect {
+    /// Split a source string into its code and, with
+    /// [`Dialect::input_separator`], the input after the first `!`.
+    pub fn split_input<'a>(&self, source: &'a str) -> (&'a str, Option<&'a str>) {
+        match source.split_once('!') {
+            Some((code, input)) if self.input_separator => (code, Some(input)),
+            _ => (source, None),
+        }
+    }
+
+    /// Parse the code in a source string into an IR, like [`IR::from_str`].
+    pub fn parse(&self, source: &str) -> Result<IR, ParseError> {
+        let (ir, errors) = parse(self.split_input(source).0, self);
+
+        // Unclosed loops are found last, innermost last.
+        let first_error = errors
+            .iter()
+            .find(|err| err.kind == ParseErrorKind::UnexpectedLoopEnd)
+            .or(errors.last());
+        match first_error {
+            Some(err) => Err(*err),
+            None => Ok(ir),
+        }
+    }
+
+    /// Parse the code in a source string into an IR, collecting every
+    /// error like [`IR::parse_all`].
+    pub fn parse_all(&self, source: &str) -> Result<IR, Vec<Diagnostic>> {
+        let code = self.split_input(source).0;
+        let (ir, errors) = parse(code, self);
+        if errors.is_empty() {
+            return Ok(ir);
+        }
+
+        let mut diagnostics: Vec<_> = errors
+            .into_iter()
+            .map(|error| Diagnostic {
+                error,
+                suggestion: suggest_fix(code, &error),
+            })
+            .collect();
+        diagnostics.sort_by_key(|diagnostic| diagnostic.error.span.start);
+        Err(diagnostics)
+    }
+}
+
+/// Parse a source string, skipping over unexpected `]`s.
+///
+/// The IR is only valid if no errors were returned. Unexpected `]`s are
+/// reported in source order, followed by the unclosed `[`s, also in
+/// source order.
+fn parse(input: &str, dialect: &Dialect) -> (IR, Vec<ParseError>) {
+    let mut tokens = Vec::new();
+    let mut jump_table = Vec::new();
+    let mut spans = Vec::new();
+    let mut loop_starts = Vec::new();
+    let mut errors = Vec::new();
+
+    for (token_pos, char) in input.char_indices() {
+        // Skip characters that are not Brainfuck instructions.
+        let token = match char {
+            '#' if dialect.debug_dump => Op::Debug,
+            _ => match Op::from_char(char) {
+                Some(token) => token,
+                None => continue,
+            },
+        };
+
+        match token {
+            Op::LoopStart => {
+                // Record the position of the `[`.
+                loop_starts.push((tokens.len(), token_pos));
+            }
+            Op::LoopEnd => {
+                // Pop the matching `[` from the stack.
+                let Some((loop_start, _)) = loop_starts.pop() else {
+                    // If the stack is empty, there is no matching `[`.
+                    errors.push(ParseError::new(
+                        input,
+                        token_pos,
+                        ParseErrorKind::UnexpectedLoopEnd,
+                    ));
+                    continue;
+                };
+
+                // Ensure the jump table is large enough.
+                let max = loop_start.max(tokens.len());
+                ensure_len(&mut jump_table, max);
+
+                // Set the jump table entries for the loop.
+                jump_table[loop_start] = tokens.len() as u32;
+                jump_table[tokens.len()] = loop_start as u32;
+            }
+            _ => {}
+        }
+
+        tokens.push(token);
+        spans.push(Span {
+            start: token_pos as u32,
+            end: token_pos as u32 + 1,
+        });
+    }
+
+    // Any loops left on the stack were never closed.
+    errors.extend(
+        loop_starts
+            .into_iter()
+            .map(|(_, token_pos)| ParseError::new(input, token_pos, ParseErrorKind::UnclosedLoop)),
+    );
+
+    let ir = IR {
+        tokens: tokens.into_boxed_slice(),
+        jump_table: jump_table.into_boxed_slice(),
+        spans: spans.into_boxed_slice(),
+    };
+    (ir, errors)
+}
+
+/// Guess where the bracket missing for `error` belongs, going by the
+/// indentation of the surrounding lines.
+///
+/// An unclosed `[` is probably closed just before the next line indented
+/// no deeper than its own, and an unexpected `]` probably opened at the
+/// end of the closest earlier line indented no deeper than its own that
+/// doesn't itself start by closing a loop. Without such a line there's
+/// nowhere to suggest.
+fn suggest_fix(source: &str, error: &ParseError) -> Option<Suggestion> {
+    let pos = error.span.start as usize;
+    let line = line_at(source, pos);
+    let line_start = line.as_ptr() as usize - source.as_ptr() as usize;
+    let line_end = line_start + line.len();
+    let indent = indent_of(line);
+
+    let pos = match error.kind {
+        ParseErrorKind::UnclosedLoop => source[line_end..]
+            .split_inclusive('\n')
+            .scan(line_end, |offset, line| {
+                let start = *offset;
+                *offset += line.len();
+                Some((start, line))
+            })
+            .find(|(_, line)| !line.trim().is_empty() && indent_of(line) <= indent)
+            .map_or(source.trim_end().len(), |(start, line)| {
+                start + line.len() - line.trim_start().len()
+            }),
+        ParseErrorKind::UnexpectedLoopEnd => source[..line_start]
+            .split_inclusive('\n')
+            .scan(0, |offset, line| {
+                let start = *offset;
+                *offset += line.len();
+                Some((start, line))
+            })
+            .filter(|(_, line)| {
+                let content = line.trim();
+                !content.is_empty() && !content.starts_with(']') && indent_of(line) <= indent
+            })
+            .last()
+            .map(|(start, line)| start + line.trim_end().len())?,
+    };
+
+    Some(Suggestion::new(source, pos))
+}
+
+/// Work out the 1-based line and column of byte offset `pos` in `source`.
+fn locate(source: &str, pos: usize) -> (u32, u32) {
+    let before = &source[..pos];
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    let line = before.matches('\n').count() as u32 + 1;
+    let column = before[line_start..].chars().count() as u32 + 1;
+    (line, column)
+}
+
+/// The line containing byte offset `pos`, without its line ending.
+fn line_at(source: &str, pos: usize) -> &str {
+    let line_start = source[..pos].rfind('\n').map_or(0, |idx| idx + 1);
+    let line_end = source[pos..]
+        .find('\n')
+        .map_or(source.len(), |idx| pos + idx);
+    source[line_start..line_end].trim_end_matches('\r')
+}
+
+/// The width of a line's leading whitespace, counting tabs as 4 columns.
+fn indent_of(line: &str) -> usize {
+    line.chars()
+        .take_while(|ch| ch.is_whitespace())
+        .map(|ch| if ch == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+impl IR {
+    /// Fold runs of `+`/`-` and `>`/`<` into counted [`Op::Add`] and
+    /// [`Op::Move`] ops.
+    ///
+    /// Runs that cancel out (e.g. `+-`) are removed entirely.
+    pub fn fold_runs(&mut self) {
+        let mut tokens = Vec::with_capacity(self.tokens.len());
+        let mut iter = self.ops().peekable();
+
+        while let Some((token, mut span)) = iter.next() {
+            if let Some(mut amount) = token.add_amount() {
+                // Keep folding while the sum still fits in an `i32`.
+                while let Some(next) = iter.peek().and_then(|(op, _)| op.add_amount()) {
+                    let Some(sum) = amount.checked_add(next) else {
+                        break;
+                    };
+                    amount = sum;
+                    span = span.join(iter.next().unwrap().1);
+                }
+                if amount != 0 {
+                    tokens.push((Op::Add { offset: 0, amount }, span));
                 }
-                Op::LoopEnd => {
-                    // Pop the matching `[` from the stack.
-                    let Some((loop_start, _)) = loop_starts.pop() else {
-                        // If the stack is empty, there is no matching `[`.
-                        return Err(ParseError::new(
-                            token_pos as u32,
-                            ParseErrorKind::UnexpectedLoopEnd,
-                        ));
+            } else if let Some(mut amount) = token.move_amount() {
+                while let Some(next) = iter.peek().and_then(|(op, _)| op.move_amount()) {
+                    let Some(sum) = amount.checked_add(next) else {
+                        break;
                     };
+                    amount = sum;
+                    span = span.join(iter.next().unwrap().1);
+                }
+                if amount != 0 {
+                    tokens.push((Op::Move(amount), span));
+                }
+            } else {
+                tokens.push((token, span));
+            }
+        }
+
+        drop(iter);
+        self.set_tokens(tokens);
+    }
 
-                    // Ensure the jump table is large enough.
-                    let max = loop_start.max(tokens.len());
-                    ensure_len(&mut jump_table, max);
+    /// Replace clear loops such as `[-]` and `[+]` with a zeroing [`Op::Set`].
+    ///
+    /// Any loop whose body only adds an odd amount to the current cell
+    /// is a clear loop, since an odd step visits every value of a
+    /// wrapping power-of-two sized cell before reaching zero.
+    pub fn recognize_clear_loops(&mut self) {
+        let mut tokens = Vec::with_capacity(self.tokens.len());
+        let mut idx = 0;
 
-                    // Set the jump table entries for the loop.
-                    jump_table[loop_start] = tokens.len() as u32;
-                    jump_table[tokens.len()] = loop_start as u32;
+        while idx < self.tokens.len() {
+            if let [Op::LoopStart, body, Op::LoopEnd, ..] = self.tokens[idx..] {
+                if body.add_amount().is_some_and(|amount| amount % 2 != 0) {
+                    let span = self.spans[idx].join(self.spans[idx + 2]);
+                    tokens.push((
+                        Op::Set {
+                            offset: 0,
+                            value: 0,
+                        },
+                        span,
+                    ));
+                    idx += 3;
+                    continue;
                 }
-                _ => {}
             }
 
-            tokens.push(token);
+            tokens.push((self.tokens[idx], self.spans[idx]));
+            idx += 1;
         }
 
-        // If there are any unclosed loops, return an error.
-        if let Some((_, token_pos)) = loop_starts.pop() {
-            return Err(ParseError::new(token_pos, ParseErrorKind::UnclosedLoop));
+        self.set_tokens(tokens);
+    }
+
+    /// Lower multiply/copy loops such as `[->+>++<<]` into a sequence of
+    /// [`Op::MulAdd`] ops followed by a zeroing [`Op::Set`].
+    ///
+    /// Only innermost loops without I/O, with a net pointer movement of
+    /// zero and that step the loop cell by exactly one are lowered.
+    pub fn recognize_mul_loops(&mut self) {
+        let mut tokens = Vec::with_capacity(self.tokens.len());
+        let mut idx = 0;
+
+        while idx < self.tokens.len() {
+            if self.tokens[idx] == Op::LoopStart {
+                let end = self.jump_table[idx] as usize;
+                if let Some(targets) = mul_loop_targets(&self.tokens[idx + 1..end]) {
+                    let span = self.spans[idx].join(self.spans[end]);
+                    tokens.extend(
+                        targets
+                            .into_iter()
+                            .map(|(offset, factor)| (Op::MulAdd { offset, factor }, span)),
+                    );
+                    tokens.push((
+                        Op::Set {
+                            offset: 0,
+                            value: 0,
+                        },
+                        span,
+                    ));
+                    idx = end + 1;
+                    continue;
+                }
+            }
+
+            tokens.push((self.tokens[idx], self.spans[idx]));
+            idx += 1;
+        }
+
+        self.set_tokens(tokens);
+    }
+
+    /// Replace scan loops such as `[>]`, `[<]` and `[>>>>]` with
+    /// [`Op::Scan`].
+    pub fn recognize_scan_loops(&mut self) {
+        let mut tokens = Vec::with_capacity(self.tokens.len());
+        let mut idx = 0;
+
+        while idx < self.tokens.len() {
+            if self.tokens[idx] == Op::LoopStart {
+                let end = self.jump_table[idx] as usize;
+                let stride = self.tokens[idx + 1..end]
+                    .iter()
+                    .try_fold(0i32, |stride, op| stride.checked_add(op.move_amount()?));
+                if let Some(stride) = stride.filter(|&stride| stride != 0) {
+                    let span = self.spans[idx].join(self.spans[end]);
+                    tokens.push((Op::Scan { stride }, span));
+                    idx = end + 1;
+                    continue;
+                }
+            }
+
+            tokens.push((self.tokens[idx], self.spans[idx]));
+            idx += 1;
         }
 
-        Ok(IR {
-            tokens: tokens.into_boxed_slice(),
-            jump_table: jump_table.into_boxed_slice(),
+        self.set_tokens(tokens);
+    }
+
+    /// Split the ops into basic blocks.
+    ///
+    /// A basic block is a maximal run of straight-line ops, i.e. ops that
+    /// neither branch nor move the pointer by a data-dependent amount.
+    /// Loop boundaries and [`Op::Scan`]s are never part of a block.
+    pub fn basic_blocks(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut idx = 0;
+        core::iter::from_fn(move || {
+            // Skip over block boundaries.
+            while idx < self.tokens.len() && self.tokens[idx].ends_block() {
+                idx += 1;
+            }
+            if idx == self.tokens.len() {
+                return None;
+            }
+
+            let start = idx;
+            while idx < self.tokens.len() && !self.tokens[idx].ends_block() {
+                idx += 1;
+            }
+            Some(start..idx)
         })
     }
+
+    /// Turn pointer movement inside basic blocks into cell offsets on the
+    /// arithmetic and I/O ops, deferring the movement to the block's end.
+    ///
+    /// For example `>+>++<<-` becomes three [`Op::Add`]s at offsets 1, 2
+    /// and 0 without moving the pointer at all.
+    pub fn defer_moves(&mut self) {
+        let mut tokens = Vec::with_capacity(self.tokens.len());
+        let mut next_idx = 0;
+
+        for block in self.basic_blocks() {
+            // Copy the boundary ops between the previous block and this one.
+            tokens.extend(self.ops().skip(next_idx).take(block.start - next_idx));
+            next_idx = block.end;
+
+            // The pointer movement that hasn't been emitted yet, and the
+            // source of the moves making it up.
+            let mut pending = 0i32;
+            let mut pending_span = None;
+
+            for (token, span) in self.ops().skip(block.start).take(block.len()) {
+                if let Some(amount) = token.move_amount() {
+                    match pending.checked_add(amount) {
+                        Some(sum) => pending = sum,
+                        None => {
+                            tokens.push((Op::Move(pending), pending_span.unwrap()));
+                            pending = amount;
+                            pending_span = None;
+                        }
+                    }
+                    pending_span =
+                        Some(pending_span.map_or(span, |pending: Span| pending.join(span)));
+                    continue;
+                }
+
+                let offset_token = match token {
+                    Op::IncByte | Op::DecByte | Op::Add { .. } => {
+                        let (offset, amount) = token.add_at().unwrap();
+                        pending
+                            .checked_add(offset)
+                            .map(|offset| Op::Add { offset, amount })
+                    }
+                    Op::Set { offset, value } => pending
+                        .checked_add(offset)
+                        .map(|offset| Op::Set { offset, value }),
+                    Op::OutByte => Some(Op::Out { offset: pending }),
+                    Op::InByte => Some(Op::In { offset: pending }),
+                    Op::Out { offset } => {
+                        pending.checked_add(offset).map(|offset| Op::Out { offset })
+                    }
+                    Op::In { offset } => {
+                        pending.checked_add(offset).map(|offset| Op::In { offset })
+                    }
+                    // Anything else is relative to the real pointer.
+                    _ => None,
+                };
+
+                match offset_token {
+                    Some(offset_token) => tokens.push((offset_token, span)),
+                    None => {
+                        if let Some(pending_span) = pending_span.take() {
+                            if pending != 0 {
+                                tokens.push((Op::Move(pending), pending_span));
+                            }
+                            pending = 0;
+                        }
+                        tokens.push((token, span));
+                    }
+                }
+            }
+
+            if let Some(pending_span) = pending_span.filter(|_| pending != 0) {
+                tokens.push((Op::Move(pending), pending_span));
+            }
+        }
+        tokens.extend(self.ops().skip(next_idx));
+
+        self.set_tokens(tokens);
+    }
+
+    /// The index of the first Op made from the source at or after byte
+    /// offset `pos`, e.g. to break at a position in the source.
+    pub fn op_at(&self, pos: usize) -> Option<usize> {
+        self.spans.iter().position(|span| span.end as usize > pos)
+    }
+
+    /// Iterate over the ops together with their source spans.
+    fn ops(&self) -> impl Iterator<Item = (Op, Span)> + '_ {
+        self.tokens.iter().copied().zip(self.spans.iter().copied())
+    }
+
+    /// Replace the ops and their spans, rebuilding the jump table.
+    fn set_tokens(&mut self, tokens: Vec<(Op, Span)>) {
+        let (tokens, spans): (Vec<_>, Vec<_>) = tokens.into_iter().unzip();
+        self.jump_table = build_jump_table(&tokens);
+        self.tokens = tokens.into_boxed_slice();
+        self.spans = spans.into_boxed_slice();
+    }
+}
+
+/// Work out the `(offset, factor)` pairs of a multiply loop body.
+///
+/// Returns `None` if the body isn't a multiply loop.
+fn mul_loop_targets(body: &[Op]) -> Option<Vec<(i32, i32)>> {
+    // Net amount added to each touched cell per iteration, in the order
+    // the cells are first touched.
+    let mut deltas: Vec<(i32, i32)> = Vec::new();
+    let mut offset = 0i32;
+
+    for &token in body {
+        if let Some((add_offset, amount)) = token.add_at() {
+            let offset = offset.checked_add(add_offset)?;
+            match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, delta)) => *delta = delta.wrapping_add(amount),
+                None => deltas.push((offset, amount)),
+            }
+        } else if let Some(amount) = token.move_amount() {
+            offset = offset.checked_add(amount)?;
+        } else {
+            // Nested loops, I/O and anything else disqualify the loop.
+            return None;
+        }
+    }
+
+    if offset != 0 {
+        return None;
+    }
+
+    // The loop runs `cell` times if it decrements the loop cell by one,
+    // and `-cell` times (mod the cell size) if it increments it by one.
+    let sign = match deltas.iter().find(|(o, _)| *o == 0) {
+        Some((_, -1)) => 1,
+        Some((_, 1)) => -1,
+        _ => return None,
+    };
+
+    Some(
+        deltas
+            .into_iter()
+            .filter(|&(offset, delta)| offset != 0 && delta != 0)
+            .map(|(offset, delta)| (offset, delta.wrapping_mul(sign)))
+            .collect(),
+    )
+}
+
+/// Build the jump table for an already balanced list of ops.
+fn build_jump_table(tokens: &[Op]) -> Box<[u32]> {
+    let mut jump_table = Vec::new();
+    let mut loop_starts = Vec::new();
+
+    for (idx, token) in tokens.iter().enumerate() {
+        match token {
+            Op::LoopStart => loop_starts.push(idx),
+            Op::LoopEnd => {
+                let loop_start = loop_starts.pop().expect("unbalanced IR");
+                ensure_len(&mut jump_table, idx);
+                jump_table[loop_start] = idx as u32;
+                jump_table[idx] = loop_start as u32;
+            }
+            _ => {}
+        }
+    }
+
+    jump_table.into_boxed_slice()
 }
 
 /// Ensure that a vector has a certain length.
@@ -163,9 +928,9 @@ fn ensure_len<T: Default + Clone>(v: &mut Vec<T>, index: usize) {
 
 const _: () = {
     use core::mem::{align_of, size_of};
-    assert!(size_of::<Op>() == 1);
-    assert!(align_of::<Op>() == 1);
+    assert!(size_of::<Op>() == 12);
+    assert!(align_of::<Op>() == 4);
 
-    assert!(size_of::<Option<Op>>() == 1);
-    assert!(align_of::<Option<Op>>() == 1);
+    assert!(size_of::<Option<Op>>() == 12);
+    assert!(align_of::<Option<Op>>() == 4);
 };
@@ -0,0 +1,324 @@
+// An x86-64 JIT that the VM runs programs with when it's enabled.
+
+use core::{arch::asm, mem, ptr};
+
+use alloc::vec::Vec;
+
+use crate::{
+    cell::Cell,
+    codegen::{
+        asm::{
+            Assembler, Cond, Label, Mem, R12, R13, R14, R15, RAX, RBP, RBX, RCX, RDI, RDX, RSI,
+            RSP, Reg,
+        },
+        lower::{Hooks, lower},
+    },
+    ir::IR,
+};
+
+/// The state the compiled code runs with and updates when it returns.
+#[repr(C)]
+pub struct State {
+    /// The VM, handed to the trampolines.
+    pub vm: *mut (),
+    /// The index in the memory buffer of the cell the pointer is on.
+    pub ptr: u64,
+    /// How many more Ops may be executed.
+    pub fuel: u64,
+    /// The index of the next Op to execute, set on return.
+    pub idx: u64,
+}
+
+// The offsets of the fields of `State`.
+const VM: i32 = 0;
+const PTR: i32 = 8;
+const FUEL: i32 = 16;
+const IDX: i32 = 24;
+
+/// Why the compiled code returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// The program has finished executing.
+    Halted,
+    /// The next Op needs the interpreter, e.g. to grow the tape, or there
+    /// isn't enough fuel left to run the next straight run of Ops.
+    Interpret,
+    /// A `,` is waiting for input.
+    AwaitingInput,
+}
+
+/// The functions the compiled code calls for I/O. Both take the VM and a
+/// pointer to the cell.
+pub struct Trampolines {
+    /// Outputs the cell.
+    pub output: unsafe extern "sysv64" fn(*mut (), *const ()),
+    /// Reads input into the cell, returning non-zero if there isn't any
+    /// yet.
+    pub input: unsafe extern "sysv64" fn(*mut (), *mut ()) -> u32,
+}
+
+/// A program compiled to machine code.
+pub struct Jit {
+    memory: ExecutableMemory,
+    /// The offset of the code for each Op that execution can start at, and
+    /// for the end of the program.
+    entries: Vec<Option<u32>>,
+}
+
+impl Jit {
+    /// Compile an IR for a tape of `C` cells, returning `None` if the
+    /// memory for it can't be mapped.
+    pub fn compile<C: Cell>(ir: &IR, trampolines: &Trampolines) -> Option<Self> {
+        let mut asm = Assembler::default();
+        let ret = asm.label();
+
+        // Save the callee-saved registers, keeping the stack aligned for
+        // calls, then load the state and jump to the entry.
+        for reg in [RBX, RBP, R12, R13, R14, R15] {
+            asm.push(reg);
+        }
+        asm.sub_i(64, RSP, 8);
+        asm.mov(64, R14, RDI);
+        asm.mov(64, R12, RSI);
+        asm.mov(64, R13, RDX);
+        asm.load(64, RBX, Mem::base(R14, PTR));
+        asm.load(64, R15, Mem::base(R14, FUEL));
+        asm.jmp_r(RCX);
+
+        // Return with the Op in rdi and the exit in rax.
+        asm.bind(ret);
+        asm.mov(64, Mem::base(R14, PTR), RBX);
+        asm.mov(64, Mem::base(R14, FUEL), R15);
+        asm.mov(64, Mem::base(R14, IDX), RDI);
+        asm.add_i(64, RSP, 8);
+        for reg in [R15, R14, R13, R12, RBP, RBX] {
+            asm.pop(reg);
+        }
+        asm.ret();
+
+        let mut exits = Exits {
+            trampolines,
+            run_end: 0,
+            exits: Vec::new(),
+        };
+        let labels = lower::<C>(&mut asm, ir, &mut exits);
+        asm.mov_ri(RDI, ir.tokens.len() as u64);
+        asm.mov_ri(RAX, HALTED);
+        asm.jmp(ret);
+
+        for (label, idx, exit, refund) in exits.exits {
+            asm.bind(label);
+            if refund > 0 {
+                asm.add_i(64, R15, refund as i32);
+            }
+            asm.mov_ri(RDI, idx as u64);
+            asm.mov_ri(RAX, exit);
+            asm.jmp(ret);
+        }
+
+        let entries = (labels.iter())
+            .map(|&label| asm.offset(label).map(|offset| offset as u32))
+            .collect();
+        Some(Self {
+            memory: ExecutableMemory::new(&asm.finish())?,
+            entries,
+        })
+    }
+
+    /// Whether execution can start at an Op.
+    pub fn is_entry(&self, idx: u32) -> bool {
+        self.entries.get(idx as usize).is_some_and(Option::is_some)
+    }
+
+    /// Run the program from an Op, on the `len` cells at `tape`.
+    ///
+    /// # Safety
+    ///
+    /// `idx` must be an entry, `tape` must point to `len` cells of the
+    /// type the program was compiled for, and `state.vm` must be what the
+    /// trampolines expect.
+    pub unsafe fn run<C>(&self, idx: u32, state: &mut State, tape: *mut C, len: usize) -> Exit {
+        type Entry<C> = unsafe extern "sysv64" fn(*mut State, *mut C, usize, *const u8) -> u64;
+
+        let offset = self.entries[idx as usize].expect("not an entry") as usize;
+        // SAFETY: The code starts with a function of this type, and the
+        // caller upholds what it needs.
+        let exit = unsafe {
+            let entry: Entry<C> = mem::transmute(self.memory.ptr);
+            entry(state, tape, len, self.memory.ptr.add(offset))
+        };
+        match exit {
+            HALTED => Exit::Halted,
+            AWAITING_INPUT => Exit::AwaitingInput,
+            _ => Exit::Interpret,
+        }
+    }
+}
+
+// The values the compiled code returns for each `Exit`.
+const HALTED: u64 = 0;
+const INTERPRET: u64 = 1;
+const AWAITING_INPUT: u64 = 2;
+
+/// The code for Ops that returns to the interpreter, and calls the
+/// trampolines.
+///
+/// Besides the registers [`lower`] uses, the code keeps the state in r14
+/// and the fuel in r15. Fuel is taken for each straight run of Ops up
+/// front, and handed back for the Ops that are left when returning in the
+/// middle of one. Straight runs start at the only Ops that are entries.
+struct Exits<'a> {
+    trampolines: &'a Trampolines,
+    /// Where the straight run of Ops being compiled ends.
+    run_end: usize,
+    /// Where to return, with the Op to return at, why and how much fuel to
+    /// hand back.
+    exits: Vec<(Label, usize, u64, usize)>,
+}
+
+impl Exits<'_> {
+    /// A label that returns at an Op, handing back the fuel for the rest of
+    /// its run.
+    fn exit(&mut self, asm: &mut Assembler, idx: usize, exit: u64) -> Label {
+        let label = asm.label();
+        self.exits.push((label, idx, exit, self.run_end - idx));
+        label
+    }
+
+    /// Call a trampoline with the VM and the address of a cell.
+    fn call(&self, asm: &mut Assembler, trampoline: usize, cell: Mem) {
+        asm.load(64, RDI, Mem::base(R14, VM));
+        asm.lea(RSI, cell);
+        asm.mov_ri(RAX, trampoline as u64);
+        asm.call_r(RAX);
+    }
+}
+
+impl Hooks for Exits<'_> {
+    /// Take the fuel for the run, returning if there isn't enough.
+    fn start_run(&mut self, asm: &mut Assembler, idx: usize, end: usize) {
+        self.run_end = end;
+        let label = self.exit(asm, idx, INTERPRET);
+        asm.sub_i(64, R15, (end - idx) as i32);
+        asm.jcc(Cond::B, label);
+    }
+
+    /// Return to the interpreter, which applies the tape policy. The
+    /// pointer only moves onto cells on the tape, so it can carry on from
+    /// there.
+    fn off_tape(&mut self, asm: &mut Assembler, idx: usize, _: Reg) -> Label {
+        self.exit(asm, idx, INTERPRET)
+    }
+
+    fn output(&mut self, asm: &mut Assembler, _: usize, cell: Mem) {
+        self.call(asm, self.trampolines.output as usize, cell);
+    }
+
+    fn input(&mut self, asm: &mut Assembler, idx: usize, cell: Mem, _: u32) {
+        self.call(asm, self.trampolines.input as usize, cell);
+        let pending = self.exit(asm, idx, AWAITING_INPUT);
+        asm.test(32, RAX, RAX);
+        asm.jcc(Cond::Ne, pending);
+    }
+
+    /// The interpreter runs the debug hook.
+    fn debug(&mut self, asm: &mut Assembler, idx: usize) {
+        let exit = self.exit(asm, idx, INTERPRET);
+        asm.jmp(exit);
+    }
+}
+
+/// Memory mapped readable and executable, holding code.
+struct ExecutableMemory {
+    ptr: *const u8,
+    len: usize,
+}
+
+// SAFETY: The memory is never written after it's mapped executable.
+unsafe impl Send for ExecutableMemory {}
+unsafe impl Sync for ExecutableMemory {}
+
+// The syscalls and flags to map memory.
+const MMAP: u64 = 9;
+const MPROTECT: u64 = 10;
+const MUNMAP: u64 = 11;
+const PROT_READ: u64 = 1;
+const PROT_WRITE: u64 = 2;
+const PROT_EXEC: u64 = 4;
+const MAP_PRIVATE: u64 = 2;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+impl ExecutableMemory {
+    /// Map memory holding `code`, or `None` if that fails.
+    fn new(code: &[u8]) -> Option<Self> {
+        let len = code.len();
+        // SAFETY: Mapping new memory doesn't touch any existing memory.
+        let ptr = unsafe {
+            syscall(
+                MMAP,
+                [
+                    0,
+                    len as u64,
+                    PROT_READ | PROT_WRITE,
+                    MAP_PRIVATE | MAP_ANONYMOUS,
+                    u64::MAX,
+                    0,
+                ],
+            )
+        };
+        // Errors are returned as negated error numbers.
+        if ptr > -4096i64 as u64 {
+            return None;
+        }
+        let memory = Self {
+            ptr: ptr as *const u8,
+            len,
+        };
+
+        // SAFETY: The memory was just mapped with room for the code, and
+        // is only ours.
+        unsafe {
+            ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, len);
+            if syscall(MPROTECT, [ptr, len as u64, PROT_READ | PROT_EXEC, 0, 0, 0]) != 0 {
+                return None;
+            }
+        }
+        Some(memory)
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        // SAFETY: The memory was mapped by `new`, and nothing refers to it
+        // once the `Jit` is gone.
+        unsafe {
+            syscall(MUNMAP, [self.ptr as u64, self.len as u64, 0, 0, 0, 0]);
+        }
+    }
+}
+
+/// Make a Linux syscall.
+///
+/// # Safety
+///
+/// The syscall must be safe to make with the arguments.
+unsafe fn syscall(number: u64, args: [u64; 6]) -> u64 {
+    let ret;
+    // SAFETY: The caller upholds the syscall's requirements.
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number => ret,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+            in("r10") args[3],
+            in("r8") args[4],
+            in("r9") args[5],
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+    ret
+}
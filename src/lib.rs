@@ -3,20 +3,73 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
+pub mod cell;
+pub mod codegen;
+pub mod debug;
+pub mod io;
 pub mod ir;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod jit;
+pub mod opt;
 pub mod vm;
 
-pub use ir::IR;
-pub use vm::{VM, VMOptions};
+pub use cell::{Cell, OutputEncoding};
+pub use debug::Debugger;
+pub use io::{Input, Output};
+pub use ir::{Dialect, IR};
+pub use opt::{OptLevel, Optimizer};
+pub use vm::{EofBehavior, RunStatus, RuntimeError, TapePolicy, TapeWindow, VM, VMOptions};
 
 #[cfg(test)]
 mod tests {
-    use core::str::FromStr;
+    use core::{cell::RefCell, str::FromStr, task::Poll};
 
-    use alloc::{string::String, vec::Vec};
+    use alloc::{collections::VecDeque, format, string::String, vec, vec::Vec};
 
-    use crate::{IR, VM, VMOptions};
+    use crate::{
+        Cell, Dialect, EofBehavior, IR, OptLevel, Optimizer, OutputEncoding, RunStatus,
+        RuntimeError, TapePolicy, VM, VMOptions,
+        ir::{Op, ParseErrorKind, Span},
+    };
+
+    const HELLO_WORLD: &str = "
+>++++++++[<+++++++++>-]<.
+>++++[<+++++++>-]<+.
++++++++..
++++.
+>>++++++[<+++++++>-]<++.
+------------.
+>++++++[<+++++++++>-]<+.
+<.
++++.
+------.
+--------.
+>>>++++[<++++++++>-]<+.";
+
+    /// Options for a VM with the given I/O on a 30,000 cell tape, for tests to
+    /// override.
+    fn with_io<I, O>(output: O, input: I) -> VMOptions<I, O> {
+        VMOptions {
+            memory_buffer_size: 30_000,
+            tape_policy: TapePolicy::Error,
+            output_encoding: OutputEncoding::Truncate,
+            eof_behavior: EofBehavior::Unchanged,
+            output,
+            input,
+            jit: false,
+        }
+    }
+
+    /// Run an IR to completion and collect its output.
+    fn run_ir(ir: IR) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let mut vm: VM<_, _> = VM::from_ir(ir, with_io(|ch| buffer.push(ch), &b""[..]));
+        vm.run().unwrap();
+        buffer
+    }
 
     #[test]
     fn test_hello_world() {
@@ -36,16 +89,814 @@ mod tests {
 
         let ir = IR::from_str(program).unwrap();
         let mut buffer = Vec::new();
-        let options = VMOptions {
-            memory_buffer_size: 30_000,
-            out_fn: &mut |ch| {
+        let options = with_io(
+            |ch| {
                 buffer.push(ch);
             },
-            in_fn: &mut || unreachable!(),
-        };
-        let mut vm = VM::from_ir(ir, options);
-        vm.run();
+            &b""[..],
+        );
+        let mut vm: VM<_, _> = VM::from_ir(ir, options);
+        vm.run().unwrap();
         let output = String::from_utf8(buffer).unwrap();
         assert_eq!(output, "Hello, World!");
     }
+
+    #[test]
+    fn test_fold_runs() {
+        let mut ir = IR::from_str("+++--[>>+<-<]+-").unwrap();
+        ir.fold_runs();
+        assert_eq!(
+            &*ir.tokens,
+            &[
+                Op::Add {
+                    offset: 0,
+                    amount: 1
+                },
+                Op::LoopStart,
+                Op::Move(2),
+                Op::Add {
+                    offset: 0,
+                    amount: 1
+                },
+                Op::Move(-1),
+                Op::Add {
+                    offset: 0,
+                    amount: -1
+                },
+                Op::Move(-1),
+                Op::LoopEnd,
+            ]
+        );
+        assert_eq!(ir.jump_table[1], 7);
+        assert_eq!(ir.jump_table[7], 1);
+
+        let mut ir = IR::from_str(HELLO_WORLD).unwrap();
+        ir.fold_runs();
+        let output = String::from_utf8(run_ir(ir)).unwrap();
+        assert_eq!(output, "Hello, World!");
+    }
+
+    #[test]
+    fn test_clear_loops() {
+        for program in ["[-]", "[+]", "[---]", "[+++++]"] {
+            let mut ir = IR::from_str(program).unwrap();
+            ir.fold_runs();
+            ir.recognize_clear_loops();
+            assert_eq!(
+                &*ir.tokens,
+                &[Op::Set {
+                    offset: 0,
+                    value: 0
+                }]
+            );
+        }
+
+        // Even steps don't always reach zero, so they must be left alone.
+        let mut ir = IR::from_str("[--]").unwrap();
+        ir.fold_runs();
+        ir.recognize_clear_loops();
+        assert_eq!(
+            &*ir.tokens,
+            &[
+                Op::LoopStart,
+                Op::Add {
+                    offset: 0,
+                    amount: -2
+                },
+                Op::LoopEnd
+            ]
+        );
+
+        // The optimized program must leave the same tape behind for every
+        // starting value, wrapping included.
+        for value in 0..=255u8 {
+            for clear in ["[-]", "[+]", "[---]"] {
+                let source = format!("{}{clear}>+<.", "+".repeat(value as usize));
+                let literal = run_ir(IR::from_str(&source).unwrap());
+                let mut ir = IR::from_str(&source).unwrap();
+                ir.recognize_clear_loops();
+                assert_eq!(run_ir(ir), literal);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mul_loops() {
+        let mut ir = IR::from_str("[->+>++<<]>[>+<+]").unwrap();
+        ir.fold_runs();
+        ir.recognize_mul_loops();
+        assert_eq!(
+            &*ir.tokens,
+            &[
+                Op::MulAdd {
+                    offset: 1,
+                    factor: 1
+                },
+                Op::MulAdd {
+                    offset: 2,
+                    factor: 2
+                },
+                Op::Set {
+                    offset: 0,
+                    value: 0
+                },
+                Op::Move(1),
+                Op::MulAdd {
+                    offset: 1,
+                    factor: -1
+                },
+                Op::Set {
+                    offset: 0,
+                    value: 0
+                },
+            ]
+        );
+
+        // Loops with I/O, nested loops or a net pointer movement stay.
+        for program in ["[->.<]", "[->[>]<]", "[->+]", "[-->+<]"] {
+            let mut ir = IR::from_str(program).unwrap();
+            ir.fold_runs();
+            let before = ir.tokens.clone();
+            ir.recognize_mul_loops();
+            assert_eq!(ir.tokens, before);
+        }
+
+        // Wrapping multiplication must match the literal program.
+        for value in [0u8, 1, 7, 100, 255] {
+            let source = format!("{}[->+++>-<<]+[>+<+]>.>.", "+".repeat(value as usize));
+            let literal = run_ir(IR::from_str(&source).unwrap());
+            let mut ir = IR::from_str(&source).unwrap();
+            ir.fold_runs();
+            ir.recognize_mul_loops();
+            assert_eq!(run_ir(ir), literal);
+        }
+    }
+
+    #[test]
+    fn test_scan_loops() {
+        let mut ir = IR::from_str("[>][<][>>>>][<<>]").unwrap();
+        ir.fold_runs();
+        ir.recognize_scan_loops();
+        assert_eq!(
+            &*ir.tokens,
+            &[
+                Op::Scan { stride: 1 },
+                Op::Scan { stride: -1 },
+                Op::Scan { stride: 4 },
+                Op::Scan { stride: -1 },
+            ]
+        );
+
+        // Lay out a run of non-zero cells long enough to cross several
+        // words, then scan both ways and mark where the pointer lands.
+        let cells = ">+".repeat(40);
+        for scan in ["<[<]", "[>]", "<<<<[<<<<]", ">[>>]"] {
+            let source = format!("{cells}{}{scan}+++.", "<".repeat(20));
+            let literal = run_ir(IR::from_str(&source).unwrap());
+            let mut ir = IR::from_str(&source).unwrap();
+            ir.fold_runs();
+            ir.recognize_scan_loops();
+            assert_eq!(run_ir(ir), literal);
+        }
+    }
+
+    #[test]
+    fn test_defer_moves() {
+        let mut ir = IR::from_str(">+>++<<-.[>>,<]>").unwrap();
+        ir.fold_runs();
+        ir.defer_moves();
+        assert_eq!(
+            &*ir.tokens,
+            &[
+                Op::Add {
+                    offset: 1,
+                    amount: 1
+                },
+                Op::Add {
+                    offset: 2,
+                    amount: 2
+                },
+                Op::Add {
+                    offset: 0,
+                    amount: -1
+                },
+                Op::Out { offset: 0 },
+                Op::LoopStart,
+                Op::In { offset: 2 },
+                Op::Move(1),
+                Op::LoopEnd,
+                Op::Move(1),
+            ]
+        );
+        assert_eq!(ir.basic_blocks().collect::<Vec<_>>(), [0..4, 5..7, 8..9]);
+    }
+
+    #[test]
+    fn test_opt_levels() {
+        let literal = IR::from_str(HELLO_WORLD).unwrap();
+
+        let mut ir = IR::from_str(HELLO_WORLD).unwrap();
+        ir.optimize(OptLevel::O0);
+        assert_eq!(ir.tokens, literal.tokens);
+
+        let mut ir = IR::from_str(HELLO_WORLD).unwrap();
+        ir.optimize(OptLevel::O1);
+        assert!(ir.tokens.len() < literal.tokens.len());
+        assert!(!ir.tokens.iter().any(|op| matches!(op, Op::Set { .. })));
+
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let mut ir = IR::from_str(HELLO_WORLD).unwrap();
+            ir.optimize(level);
+            let output = String::from_utf8(run_ir(ir)).unwrap();
+            assert_eq!(output, "Hello, World!");
+        }
+
+        // Folded moves are only checked where they end up.
+        let excursion = [
+            (
+                OptLevel::O0,
+                Err(RuntimeError::TapeUnderflow { op_idx: 0, ptr: -1 }),
+            ),
+            (OptLevel::O1, Ok(RunStatus::Halted)),
+            (OptLevel::O2, Ok(RunStatus::Halted)),
+        ];
+        for (level, expected) in excursion {
+            let mut ir = IR::from_str("<<>>").unwrap();
+            ir.optimize(level);
+            let mut vm: VM<_, _> = VM::from_ir(ir, with_io(|_| {}, &b""[..]));
+            assert_eq!(vm.run(), expected, "{level:?}");
+        }
+
+        // Disabling a pass leaves the others running.
+        let optimizer = Optimizer {
+            defer_moves: false,
+            ..Optimizer::new(OptLevel::O2)
+        };
+        let mut ir = IR::from_str("[-]>+<").unwrap();
+        optimizer.run(&mut ir);
+        assert_eq!(
+            &*ir.tokens,
+            &[
+                Op::Set {
+                    offset: 0,
+                    value: 0
+                },
+                Op::Move(1),
+                Op::Add {
+                    offset: 0,
+                    amount: 1
+                },
+                Op::Move(-1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_error_spans() {
+        let source = "+++\n\t[>+[<-]\n]]x";
+        let err = IR::from_str(source).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedLoopEnd);
+        assert_eq!((err.line, err.column), (3, 2));
+        assert_eq!(err.span, Span { start: 14, end: 15 });
+        assert_eq!(
+            format!("{}", err.render(source)),
+            "error: unexpected `]`, there is no matching `[`
+ --> 3:2
+  |
+3 | ]]x
+  |  ^"
+        );
+
+        // Unclosed loops point at the innermost unmatched `[`.
+        let source = "+[>\n\t[-<[+]\n";
+        let err = IR::from_str(source).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnclosedLoop);
+        assert_eq!((err.line, err.column), (2, 2));
+        assert_eq!(
+            format!("{}", err.render(source)),
+            "error: unclosed loop, this `[` has no matching `]`
+ --> 2:2
+  |
+2 | \t[-<[+]
+  | \t^"
+        );
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let source = "\
++[
+    >[-]
+    >[
+        <+
+]
+]]
++[";
+        let diagnostics = IR::parse_all(source).unwrap_err();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let suggestion = diagnostic.suggestion.unwrap();
+                (
+                    diagnostic.error.kind,
+                    (diagnostic.error.line, diagnostic.error.column),
+                    (suggestion.line, suggestion.column),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                // The `[` on line 3 is closed by line 5, so line 6 has one
+                // `]` too many, which probably opened at the end of line 1.
+                (ParseErrorKind::UnexpectedLoopEnd, (6, 2), (1, 3)),
+                (ParseErrorKind::UnclosedLoop, (7, 2), (7, 3)),
+            ]
+        );
+
+        assert!(
+            format!("{}", diagnostics[1].render(source))
+                .ends_with("= help: the matching `]` probably belongs at 7:3")
+        );
+
+        // A stray `]` with nothing before it has nowhere to open.
+        let stray = &IR::parse_all("]+[[").unwrap_err()[0];
+        assert_eq!(stray.error.kind, ParseErrorKind::UnexpectedLoopEnd);
+        assert_eq!(stray.suggestion, None);
+
+        // The single error from `from_str` is the first of the lot.
+        let err = IR::from_str(source).unwrap_err();
+        assert_eq!(err, diagnostics[0].error);
+    }
+
+    #[test]
+    fn test_tape_policies() {
+        /// Run a program on a tape of `size` cells, returning its output or
+        /// error.
+        fn run_sized(
+            source: &str,
+            size: u32,
+            tape_policy: TapePolicy,
+        ) -> Result<Vec<u8>, RuntimeError> {
+            let mut buffer = Vec::new();
+            let options = VMOptions {
+                memory_buffer_size: size,
+                tape_policy,
+                ..with_io(|ch| buffer.push(ch), &b""[..])
+            };
+            let mut vm: VM<_, _> = VM::new(source, options).unwrap();
+            vm.run()?;
+            Ok(buffer)
+        }
+
+        /// Run a program on a tiny tape, returning its output or error.
+        fn run_policy(source: &str, tape_policy: TapePolicy) -> Result<Vec<u8>, RuntimeError> {
+            run_sized(source, 4, tape_policy)
+        }
+
+        assert_eq!(
+            run_policy("+<", TapePolicy::Error),
+            Err(RuntimeError::TapeUnderflow { op_idx: 1, ptr: -1 })
+        );
+        // Errors point at the source of the op.
+        let source = "+\n\t<";
+        let err = run_policy(source, TapePolicy::Error).unwrap_err();
+        assert_eq!(
+            format!("{}", err.render(&IR::from_str(source).unwrap(), source)),
+            "error: tape underflow: pointer moved to cell -1
+ --> 2:2
+  |
+2 | \t<
+  | \t^"
+        );
+        assert_eq!(
+            run_policy(">>>>", TapePolicy::Error),
+            Err(RuntimeError::TapeOverflow { op_idx: 3, ptr: 4 })
+        );
+        assert_eq!(
+            run_policy("+[>+]", TapePolicy::Error),
+            Err(RuntimeError::TapeOverflow { op_idx: 2, ptr: 4 })
+        );
+
+        assert_eq!(run_policy("+<+++.>.", TapePolicy::Wrap), Ok(vec![3, 1]));
+        assert_eq!(run_policy(">>>>+<<<<.", TapePolicy::Wrap), Ok(vec![1]));
+
+        let grow = TapePolicy::Grow { max_size: None };
+        assert_eq!(run_policy(">>>>>>>>>+.", grow), Ok(vec![1]));
+        assert_eq!(
+            run_policy(">+<<", grow),
+            Err(RuntimeError::TapeUnderflow { op_idx: 3, ptr: -1 })
+        );
+
+        let both = TapePolicy::Bidirectional { max_size: None };
+        assert_eq!(run_policy("+<<<<<<<<<++.>>>>>>>>>.", both), Ok(vec![2, 1]));
+
+        // Growing stops at the ceiling.
+        let grow = TapePolicy::Grow { max_size: Some(6) };
+        assert_eq!(run_policy(">>>>>+.", grow), Ok(vec![1]));
+        assert_eq!(
+            run_policy(">>>>>>+.", grow),
+            Err(RuntimeError::TapeOverflow { op_idx: 5, ptr: 6 })
+        );
+        // An empty tape still has a cell for the pointer to start on.
+        for tape_policy in [TapePolicy::Error, TapePolicy::Wrap, grow] {
+            assert_eq!(run_sized("++.", 0, tape_policy), Ok(vec![2]));
+        }
+
+        let both = TapePolicy::Bidirectional { max_size: Some(6) };
+        assert_eq!(run_policy("<<+>>>+.", both), Ok(vec![1]));
+        assert_eq!(
+            run_policy("<<+>>>>>>+.", both),
+            Err(RuntimeError::TapeOverflow { op_idx: 8, ptr: 4 })
+        );
+
+        // Optimized scans follow the policy too.
+        for stride in [">", ">>>", "<", "<<<"] {
+            let source = format!(">+>+>+[{stride}]+.");
+            let mut ir = IR::from_str(&source).unwrap();
+            ir.optimize(OptLevel::O2);
+            let mut buffer = Vec::new();
+            let options = VMOptions {
+                memory_buffer_size: 4,
+                tape_policy: TapePolicy::Wrap,
+                ..with_io(|ch| buffer.push(ch), &b""[..])
+            };
+            VM::<_, _, u8>::from_ir(ir, options).run().unwrap();
+            assert_eq!(buffer, [1]);
+        }
+    }
+
+    #[test]
+    fn test_memory_dump() {
+        let options = VMOptions {
+            memory_buffer_size: 2,
+            tape_policy: TapePolicy::Bidirectional { max_size: None },
+            ..with_io(|_| unreachable!(), &b""[..])
+        };
+        let mut vm: VM<_, _> = VM::new("+<<<++>>>>+++", options).unwrap();
+        vm.run().unwrap();
+
+        let cells: Vec<_> = vm.memory_dump().filter(|&(_, value)| value != 0).collect();
+        assert_eq!(cells, [(-3, 2), (0, 1), (1, 3)]);
+    }
+
+    #[test]
+    fn test_cell_widths() {
+        /// Run a program with the given cell type, collecting its output.
+        fn run_cells<C: Cell>(source: &str, output_encoding: OutputEncoding) -> Vec<u8> {
+            let mut ir = IR::from_str(source).unwrap();
+            ir.optimize(OptLevel::O2);
+            let mut buffer = Vec::new();
+            let options = VMOptions {
+                memory_buffer_size: 16,
+                output_encoding,
+                ..with_io(|ch| buffer.push(ch), &b""[..])
+            };
+            VM::<_, _, C>::from_ir(ir, options).run().unwrap();
+            buffer
+        }
+
+        // 300 doesn't fit in a byte, so only wider cells keep it.
+        let source = format!("{}[->+>++<<]>.>.", "+".repeat(300));
+        assert_eq!(run_cells::<u8>(&source, OutputEncoding::Truncate), [44, 88]);
+        assert_eq!(
+            run_cells::<u16>(&source, OutputEncoding::Truncate),
+            [44, 88]
+        );
+        assert_eq!(
+            run_cells::<u16>(&source, OutputEncoding::CodePoint),
+            "\u{12c}\u{258}".as_bytes()
+        );
+
+        // Each width wraps at its own size.
+        assert_eq!(
+            run_cells::<u8>("-.", OutputEncoding::CodePoint),
+            "\u{ff}".as_bytes()
+        );
+        assert_eq!(
+            run_cells::<u16>("-.", OutputEncoding::CodePoint),
+            "\u{ffff}".as_bytes()
+        );
+        assert_eq!(
+            run_cells::<u32>("-.", OutputEncoding::CodePoint),
+            "\u{fffd}".as_bytes()
+        );
+        assert_eq!(
+            run_cells::<u64>("-[>+<-]>.", OutputEncoding::Truncate),
+            [0xff]
+        );
+
+        // Scans work on every width.
+        assert_eq!(
+            run_cells::<u32>("+>+>+<<[>]+<.", OutputEncoding::Truncate),
+            [1]
+        );
+    }
+
+    #[test]
+    fn test_eof_behavior() {
+        /// Read past the end of `input` into a cell holding 3.
+        fn run_eof<C: Cell>(input: &[u8], eof_behavior: EofBehavior) -> Vec<u8> {
+            let mut input = input.iter().copied();
+            let mut buffer = Vec::new();
+            let options = VMOptions {
+                memory_buffer_size: 1,
+                output_encoding: OutputEncoding::CodePoint,
+                eof_behavior,
+                ..with_io(|ch| buffer.push(ch), || Poll::Ready(input.next()))
+            };
+            let mut vm: VM<_, _, C> = VM::new("+++,.,.", options).unwrap();
+            vm.run().unwrap();
+            buffer
+        }
+
+        assert_eq!(run_eof::<u8>(b"a", EofBehavior::Unchanged), b"aa");
+        assert_eq!(run_eof::<u8>(b"", EofBehavior::Unchanged), [3, 3]);
+        assert_eq!(run_eof::<u8>(b"a", EofBehavior::Zero), b"a\0");
+        assert_eq!(
+            run_eof::<u8>(b"a", EofBehavior::MinusOne),
+            "a\u{ff}".as_bytes()
+        );
+        assert_eq!(
+            run_eof::<u16>(b"a", EofBehavior::MinusOne),
+            "a\u{ffff}".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_run_for() {
+        // Input shows up a byte at a time, `None` means none is ready yet.
+        let input = RefCell::new(None);
+        let mut buffer = Vec::new();
+        let options = VMOptions {
+            memory_buffer_size: 4,
+            eof_behavior: EofBehavior::Zero,
+            ..with_io(
+                |ch| buffer.push(ch),
+                || match input.borrow_mut().take() {
+                    Some(byte) => Poll::Ready(byte),
+                    None => Poll::Pending,
+                },
+            )
+        };
+        // Echo input until EOF, then spin forever.
+        let mut vm: VM<_, _> = VM::new(",[.,]+[]", options).unwrap();
+
+        assert_eq!(vm.run_for(100), Ok(RunStatus::AwaitingInput));
+        assert_eq!(vm.run_for(100), Ok(RunStatus::AwaitingInput));
+        *input.borrow_mut() = Some(Some(b'h'));
+        assert_eq!(vm.run_for(2), Ok(RunStatus::OutOfFuel));
+        assert_eq!(vm.run_for(100), Ok(RunStatus::AwaitingInput));
+        *input.borrow_mut() = Some(Some(b'i'));
+        assert_eq!(vm.run(), Ok(RunStatus::AwaitingInput));
+        *input.borrow_mut() = Some(None);
+        assert_eq!(vm.run_for(1_000), Ok(RunStatus::OutOfFuel));
+        drop(vm);
+        assert_eq!(buffer, b"hi");
+
+        let options = VMOptions {
+            memory_buffer_size: 4,
+            eof_behavior: EofBehavior::Zero,
+            ..with_io(|_| {}, &b""[..])
+        };
+        let mut vm: VM<_, _> = VM::new("++[-]", options).unwrap();
+        assert_eq!(vm.run_for(3), Ok(RunStatus::OutOfFuel));
+        assert_eq!(vm.run_for(100), Ok(RunStatus::Halted));
+        assert_eq!(vm.run_for(100), Ok(RunStatus::Halted));
+    }
+
+    #[test]
+    fn test_owned_io() {
+        /// An owned VM can be stored anywhere and moved across threads.
+        struct Session {
+            vm: VM<VecDeque<u8>, Vec<u8>>,
+        }
+
+        fn assert_send<T: Send>(_: &T) {}
+
+        let options = VMOptions {
+            memory_buffer_size: 4,
+            eof_behavior: EofBehavior::Zero,
+            ..with_io(Vec::new(), VecDeque::new())
+        };
+        let mut session = Session {
+            vm: VM::new(",[+.,]", options).unwrap(),
+        };
+        assert_send(&session);
+
+        assert_eq!(session.vm.run(), Ok(RunStatus::AwaitingInput));
+        session.vm.input_mut().extend(b"HAL");
+        assert_eq!(session.vm.run(), Ok(RunStatus::AwaitingInput));
+        assert_eq!(session.vm.output(), b"IBM");
+
+        // Byte slices end the input once they run out.
+        let options = VMOptions {
+            memory_buffer_size: 4,
+            eof_behavior: EofBehavior::Zero,
+            ..with_io(Vec::new(), &b"HAL"[..])
+        };
+        let mut vm: VM<_, _> = VM::new(",[+.,]", options).unwrap();
+        assert_eq!(vm.run(), Ok(RunStatus::Halted));
+        assert_eq!(vm.into_io(), (&b""[..], b"IBM".to_vec()));
+    }
+
+    #[test]
+    fn test_load() {
+        let options = VMOptions {
+            memory_buffer_size: 4,
+            eof_behavior: EofBehavior::Zero,
+            ..with_io(Vec::new(), &b""[..])
+        };
+        let mut vm: VM<_, _> = VM::new("+++>++", options).unwrap();
+        assert_eq!(vm.run(), Ok(RunStatus::Halted));
+        assert_eq!(vm.pointer(), 1);
+
+        // A new program picks up the tape where the last one left it.
+        vm.load(IR::from_str("[<+>-]<.").unwrap());
+        assert_eq!(vm.run(), Ok(RunStatus::Halted));
+        assert_eq!(vm.pointer(), 0);
+        assert_eq!(vm.output(), &[5]);
+        let tape: Vec<_> = vm.memory_dump().map(|(_, value)| value).collect();
+        assert_eq!(tape, [5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_debugger() {
+        use crate::debug::{Breakpoint, Comparison, Condition, Debugger, Stop};
+
+        let options = VMOptions {
+            memory_buffer_size: 4,
+            eof_behavior: EofBehavior::Zero,
+            ..with_io(Vec::new(), &b""[..])
+        };
+        let source = "+++[>++<-]>[-]";
+        let mut ir = IR::from_str(source).unwrap();
+        ir.optimize(OptLevel::O1);
+        let vm: VM<_, _> = VM::from_ir(ir, options);
+        let mut debugger = Debugger::new(vm);
+
+        // Break at the `-` in the first loop once it has run twice.
+        let op_idx = debugger.vm().ir().op_at(source.find('-').unwrap()).unwrap();
+        assert_eq!(
+            debugger.vm().ir().tokens[op_idx],
+            Op::Add {
+                offset: 0,
+                amount: -1
+            }
+        );
+        debugger.breakpoints.push(Breakpoint {
+            op_idx: op_idx as u32,
+            condition: Some(Condition {
+                cell: Some(1),
+                comparison: Comparison::Ge,
+                value: 4,
+            }),
+        });
+        assert_eq!(debugger.resume(), Ok(Stop::Breakpoint(0)));
+        assert_eq!(debugger.vm().pointer(), 0);
+        assert_eq!(debugger.vm().cell(0), 2);
+        assert_eq!(debugger.vm().cell(1), 4);
+
+        // Finish the loop, then step over the next one.
+        debugger.breakpoints.clear();
+        assert_eq!(debugger.finish_loop(), Ok(Stop::Done));
+        assert_eq!(debugger.vm().cell(1), 6);
+        assert_eq!(debugger.step(), Ok(Stop::Done));
+        assert_eq!(debugger.step_over(), Ok(Stop::Halted));
+        assert_eq!(debugger.vm().memory_buffer(), [0, 0, 0, 0]);
+
+        // Watch the cell the pointer was left on as another program runs.
+        debugger.vm_mut().load(IR::from_str("+>+++").unwrap());
+        debugger.watchpoints.push(1);
+        assert_eq!(
+            debugger.resume(),
+            Ok(Stop::Watchpoint {
+                cell: 1,
+                old: 0,
+                new: 1
+            })
+        );
+        assert_eq!(debugger.vm().memory_buffer_ptr(), 1);
+        assert_eq!(debugger.vm().current_token_idx(), 1);
+    }
+
+    #[test]
+    fn test_dialect() {
+        use alloc::{string::ToString, sync::Arc};
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        // `#` and `!` are comments unless enabled.
+        let source = "+>++#<#!input";
+        assert_eq!(IR::from_str(source).unwrap().tokens.len(), 5);
+        let dialect = Dialect {
+            debug_dump: true,
+            input_separator: true,
+        };
+        assert_eq!(dialect.split_input(source), ("+>++#<#", Some("input")));
+
+        let mut ir = dialect.parse(source).unwrap();
+        assert_eq!(ir.tokens.iter().filter(|&&op| op == Op::Debug).count(), 2);
+        // Moves aren't deferred past a dump, so it sees the real pointer.
+        ir.optimize(OptLevel::O2);
+
+        let options = VMOptions {
+            memory_buffer_size: 2,
+            eof_behavior: EofBehavior::Zero,
+            ..with_io(Vec::new(), &b""[..])
+        };
+        let mut vm: VM<_, _> = VM::from_ir(ir, options);
+        let dumps = Arc::new(AtomicUsize::new(0));
+        let hook_dumps = Arc::clone(&dumps);
+        vm.set_debug_hook(1, move |_, window| {
+            let expected = ["ptr 1 | cells 0..=1: 1 [2]", "ptr 0 | cells 0..=1: [1] 2"];
+            let idx = hook_dumps.fetch_add(1, Ordering::Relaxed);
+            assert_eq!(window.to_string(), expected[idx]);
+        });
+        assert_eq!(vm.run(), Ok(RunStatus::Halted));
+        assert_eq!(dumps.load(Ordering::Relaxed), 2);
+    }
+
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    #[test]
+    fn test_jit() {
+        type Session = VM<VecDeque<u8>, Vec<u8>, u16>;
+        /// How a run ended, the nonzero cells, the current op and the output.
+        type Outcome = (
+            Result<RunStatus, RuntimeError>,
+            Vec<(i64, u16)>,
+            u32,
+            Vec<u8>,
+        );
+
+        /// Run a program in slices of `fuel` steps, feeding it `input` a byte
+        /// at a time whenever it waits for more.
+        fn run_sliced(
+            source: &str,
+            tape_policy: TapePolicy,
+            jit: bool,
+            fuel: u64,
+            input: &[u8],
+        ) -> Outcome {
+            let mut ir = IR::from_str(source).unwrap();
+            ir.optimize(OptLevel::O2);
+            let options = VMOptions {
+                memory_buffer_size: 4,
+                tape_policy,
+                jit,
+                ..with_io(Vec::new(), VecDeque::new())
+            };
+            let mut vm = Session::from_ir(ir, options);
+            let mut input = input.iter();
+            let result = loop {
+                match vm.run_for(fuel) {
+                    Ok(RunStatus::OutOfFuel) => {}
+                    Ok(RunStatus::AwaitingInput) if input.len() > 0 => {
+                        vm.input_mut().extend(input.next());
+                    }
+                    result => break result,
+                }
+            };
+            let dump = vm.memory_dump().filter(|&(_, value)| value != 0).collect();
+            let idx = vm.current_token_idx();
+            (result, dump, idx, vm.into_io().1)
+        }
+
+        let grow = TapePolicy::Bidirectional { max_size: None };
+        let cases = [
+            (HELLO_WORLD, TapePolicy::Error),
+            (
+                "++++[>>>>+<<<<-]>>>>[>>+<<-]>>.",
+                TapePolicy::Grow { max_size: None },
+            ),
+            ("+++[<<<+++[>+<-]>>]<<.#", grow),
+            (">>>>+[>+]", TapePolicy::Error),
+            ("<", TapePolicy::Error),
+            (",[>,]<[.<]", TapePolicy::Error),
+        ];
+        for (source, tape_policy) in cases {
+            let expected = run_sliced(source, tape_policy, false, u64::MAX, b"abc");
+            for fuel in [1, 3, 7, 1_000, u64::MAX] {
+                assert_eq!(
+                    run_sliced(source, tape_policy, true, fuel, b"abc"),
+                    expected,
+                    "{source} with fuel {fuel}"
+                );
+            }
+        }
+        assert_eq!(
+            run_sliced(HELLO_WORLD, TapePolicy::Error, true, 100, b"").3,
+            b"Hello, World!"
+        );
+
+        // An empty tape gets a cell before any compiled code reads it.
+        let options = VMOptions {
+            memory_buffer_size: 0,
+            tape_policy: TapePolicy::Grow { max_size: None },
+            jit: true,
+            ..with_io(Vec::new(), VecDeque::new())
+        };
+        let mut vm = Session::new("+[>+<-]>.", options).unwrap();
+        assert_eq!(vm.run(), Ok(RunStatus::Halted));
+        assert_eq!(vm.output(), &[1]);
+    }
 }
@@ -1,56 +1,365 @@
 // The main entry point for the BFC interpreter.
+
+mod cli;
+mod debug_cli;
+mod repl;
+
 use std::{
     env, fs,
-    io::{self, Read, Write},
-    str::FromStr,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    process::ExitCode,
+};
+
+use bfc::{
+    Cell, IR, Input, Output, OutputEncoding, RunStatus, VM, VMOptions,
+    codegen::{self, CodegenOptions},
+    io::{ReadInput, WriteOutput},
+    ir::Op,
 };
+use cli::{Args, CellWidth, Command, Source, Target, USAGE};
 
-use bfc::{IR, VM, VMOptions};
+/// The exit code for an invalid command line.
+const USAGE_ERROR: u8 = 2;
 
-fn main() -> io::Result<()> {
-    // Parse command-line arguments to get the file path.
-    let mut args = env::args();
-    args.next(); // Skip the program name.
-    let file_path = match args.next() {
-        Some(path) => path,
-        None => {
-            eprintln!("Error: No input file provided.");
-            // Exit gracefully if no file is provided.
-            return Ok(());
+/// How many cells on either side of the pointer `#` prints.
+const DUMP_RADIUS: u32 = 8;
+
+/// The width `bfc fmt` wraps long lines of code at.
+const LINE_WIDTH: usize = 80;
+
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: {err}.\n\nRun `bfc help` for usage.");
+            return ExitCode::from(USAGE_ERROR);
         }
     };
 
-    // Read the Brainfuck source code from the file.
-    let source = fs::read_to_string(file_path)?;
+    match execute(&args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err}.");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    // Define the output function for the VM.
-    fn putchar(ch: u8) {
-        print!("{}", ch as char);
+/// Execute the command in `args`.
+fn execute(args: &Args) -> io::Result<ExitCode> {
+    match args.command {
+        Command::Help => {
+            print!("{USAGE}");
+            return Ok(ExitCode::SUCCESS);
+        }
+        Command::Version => {
+            println!("bfc {}", env!("CARGO_PKG_VERSION"));
+            return Ok(ExitCode::SUCCESS);
+        }
+        Command::Repl => {
+            let preload = args.source.as_ref().map(read_source).transpose()?;
+            return match args.cell_width {
+                CellWidth::U8 => repl::repl::<u8>(args, preload),
+                CellWidth::U16 => repl::repl::<u16>(args, preload),
+                CellWidth::U32 => repl::repl::<u32>(args, preload),
+                CellWidth::U64 => repl::repl::<u64>(args, preload),
+            };
+        }
+        _ => {}
     }
 
-    // Define the input function for the VM.
-    fn getchar() -> u8 {
-        io::stdout().flush().unwrap();
-        let mut buffer = [0; 1]; // Read one byte from stdin.
-        io::stdin().read_exact(&mut buffer).unwrap();
-        buffer[0]
+    // Read the Brainfuck source code and parse it into an IR.
+    let source = read_source(args.source.as_ref().expect("a source is required"))?;
+    let mut ir = match args.dialect.parse_all(&source) {
+        Ok(ir) => ir,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                eprintln!("{}\n", diagnostic.render(&source));
+            }
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    // With `--input-separator`, the program's input may follow the code.
+    let (_, input) = args.dialect.split_input(&source);
+
+    match args.command {
+        Command::Check => {}
+        Command::Fmt => {
+            let code = with_input(format_ir(&ir), input);
+            io::stdout().write_all(code.as_bytes())?;
+        }
+        Command::DumpIr => {
+            ir.optimize(args.opt_level);
+            io::stdout().write_all(dump_ir(&ir).as_bytes())?;
+        }
+        Command::Compile => {
+            let options = CodegenOptions {
+                tape_size: args
+                    .tape_size
+                    .unwrap_or(CodegenOptions::default().tape_size),
+                eof_behavior: args.eof_behavior,
+            };
+            let code = match args.target {
+                Target::Bf => with_input(compile_bf(&ir), input).into_bytes(),
+                Target::C => {
+                    ir.optimize(args.opt_level);
+                    match args.cell_width {
+                        CellWidth::U8 => codegen::c::compile::<u8>(&ir, &options),
+                        CellWidth::U16 => codegen::c::compile::<u16>(&ir, &options),
+                        CellWidth::U32 => codegen::c::compile::<u32>(&ir, &options),
+                        CellWidth::U64 => codegen::c::compile::<u64>(&ir, &options),
+                    }
+                    .into_bytes()
+                }
+                Target::Rust => {
+                    ir.optimize(args.opt_level);
+                    match args.cell_width {
+                        CellWidth::U8 => codegen::rust::compile::<u8>(&ir, &options),
+                        CellWidth::U16 => codegen::rust::compile::<u16>(&ir, &options),
+                        CellWidth::U32 => codegen::rust::compile::<u32>(&ir, &options),
+                        CellWidth::U64 => codegen::rust::compile::<u64>(&ir, &options),
+                    }
+                    .into_bytes()
+                }
+                Target::X86_64 => {
+                    ir.optimize(args.opt_level);
+                    match args.cell_width {
+                        CellWidth::U8 => codegen::x86_64::compile::<u8>(&ir, &options),
+                        CellWidth::U16 => codegen::x86_64::compile::<u16>(&ir, &options),
+                        CellWidth::U32 => codegen::x86_64::compile::<u32>(&ir, &options),
+                        CellWidth::U64 => codegen::x86_64::compile::<u64>(&ir, &options),
+                    }
+                    .into_bytes()
+                }
+                Target::Elf => {
+                    ir.optimize(args.opt_level);
+                    match args.cell_width {
+                        CellWidth::U8 => codegen::elf::compile::<u8>(&ir, &options),
+                        CellWidth::U16 => codegen::elf::compile::<u16>(&ir, &options),
+                        CellWidth::U32 => codegen::elf::compile::<u32>(&ir, &options),
+                        CellWidth::U64 => codegen::elf::compile::<u64>(&ir, &options),
+                    }
+                }
+            };
+            match &args.output {
+                Some(path) if args.target == Target::Elf => write_executable(path, &code)?,
+                Some(path) => fs::write(path, code)?,
+                None => io::stdout().write_all(&code)?,
+            }
+        }
+        Command::Run => {
+            ir.optimize(args.opt_level);
+            return match args.cell_width {
+                CellWidth::U8 => run::<u8>(ir, &source, args, input),
+                CellWidth::U16 => run::<u16>(ir, &source, args, input),
+                CellWidth::U32 => run::<u32>(ir, &source, args, input),
+                CellWidth::U64 => run::<u64>(ir, &source, args, input),
+            };
+        }
+        Command::Debug => {
+            ir.optimize(args.opt_level);
+            return match args.cell_width {
+                CellWidth::U8 => debug_cli::debug::<u8>(ir, &source, input, args),
+                CellWidth::U16 => debug_cli::debug::<u16>(ir, &source, input, args),
+                CellWidth::U32 => debug_cli::debug::<u32>(ir, &source, input, args),
+                CellWidth::U64 => debug_cli::debug::<u64>(ir, &source, input, args),
+            };
+        }
+        Command::Repl | Command::Help | Command::Version => unreachable!(),
     }
 
-    // Parse the source code into an IR.
-    let ir = IR::from_str(&source).unwrap();
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Write a compiled program to `path`, and make it executable.
+fn write_executable(path: &Path, code: &[u8]) -> io::Result<()> {
+    fs::write(path, code)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Read the program's source code.
+fn read_source(source: &Source) -> io::Result<String> {
+    match source {
+        Source::File(path) => fs::read_to_string(path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("cannot read `{}`: {err}", path.display()),
+            )
+        }),
+        Source::Stdin => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source)?;
+            Ok(source)
+        }
+        Source::Inline(code) => Ok(code.clone()),
+    }
+}
+
+/// Run the program parsed from `source` on a tape of `C` cells, reading
+/// `input` if it's given and stdin otherwise.
+fn run<C: Cell>(ir: IR, source: &str, args: &Args, input: Option<&str>) -> io::Result<ExitCode> {
+    match input {
+        Some(input) => run_with::<C, _>(ir, source, args, input.as_bytes()),
+        None => run_with::<C, _>(ir, source, args, ReadInput(io::stdin().lock())),
+    }
+}
 
-    // Set up the VM options.
+/// Run the program parsed from `source` on a tape of `C` cells, writing its
+/// output to stdout.
+fn run_with<C: Cell, I: Input>(
+    ir: IR,
+    source: &str,
+    args: &Args,
+    input: I,
+) -> io::Result<ExitCode> {
+    let (memory_buffer_size, tape_policy) = args.tape();
     let options = VMOptions {
-        memory_buffer_size: 30_000, // Standard Brainfuck memory size.
-        out_fn: &mut putchar,
-        in_fn: &mut getchar,
+        memory_buffer_size,
+        tape_policy,
+        output_encoding: OutputEncoding::Truncate,
+        eof_behavior: args.eof_behavior,
+        // Write raw bytes through a buffer, the VM flushes it before
+        // reading input.
+        output: WriteOutput::new(BufWriter::new(io::stdout().lock())),
+        input,
+        jit: args.jit,
+    };
+    let mut vm = VM::<_, _, C>::from_ir(ir, options);
+    dump_to_stderr(&mut vm);
+
+    // Run the VM in slices, so programs that never halt still stop once
+    // stdout is closed.
+    let result = loop {
+        match vm.run_for(1 << 16) {
+            Ok(RunStatus::OutOfFuel) if !vm.output().failed() => continue,
+            result => break result,
+        }
     };
 
-    // Create a new VM from the IR and options.
-    let mut vm = VM::from_ir(ir, options);
+    // Find where the program failed while the VM still has the IR.
+    let report = result.as_ref().err().map(|err| err.render(vm.ir(), source));
 
-    // Run the VM.
-    vm.run();
+    // Flush whatever output is left, even if the program failed.
+    let (_, output) = vm.into_io();
+    output.finish()?;
 
-    Ok(())
+    match report {
+        None => Ok(ExitCode::SUCCESS),
+        Some(report) => {
+            eprintln!("{report}");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Print the cells around the pointer to stderr for each `#`.
+pub fn dump_to_stderr<I: Input, O: Output, C: Cell>(vm: &mut VM<I, O, C>) {
+    vm.set_debug_hook(DUMP_RADIUS, |op_idx, window| {
+        eprintln!("# at op {op_idx}: {window}");
+    });
+}
+
+/// Append the program's input to its code, if it had any.
+fn with_input(mut code: String, input: Option<&str>) -> String {
+    if let Some(input) = input {
+        code.push('!');
+        code.push_str(input);
+    }
+    code
+}
+
+/// Format an unoptimized IR as source code, without comments.
+///
+/// Loops that contain other loops get their brackets on lines of their own
+/// and their body indented, innermost loops are kept on a single line.
+fn format_ir(ir: &IR) -> String {
+    let mut formatted = String::new();
+    let mut line = String::new();
+    let mut depth = 0;
+    let mut flush = |line: &mut String, depth: usize| {
+        if !line.is_empty() {
+            formatted.extend(std::iter::repeat_n("    ", depth));
+            formatted.push_str(line);
+            formatted.push('\n');
+            line.clear();
+        }
+    };
+
+    let mut idx = 0;
+    while idx < ir.tokens.len() {
+        let chunk = match ir.tokens[idx] {
+            Op::LoopStart => {
+                let end = ir.jump_table[idx] as usize;
+                if ir.tokens[idx + 1..end].contains(&Op::LoopStart) {
+                    flush(&mut line, depth);
+                    line.push('[');
+                    flush(&mut line, depth);
+                    depth += 1;
+                    String::new()
+                } else {
+                    // An innermost loop, keep it in one piece.
+                    let chunk = ir.tokens[idx..=end].iter().filter_map(|op| op.into_char());
+                    idx = end;
+                    chunk.collect()
+                }
+            }
+            Op::LoopEnd => {
+                flush(&mut line, depth);
+                depth -= 1;
+                line.push(']');
+                flush(&mut line, depth);
+                String::new()
+            }
+            op => op.into_char().into_iter().collect(),
+        };
+        idx += 1;
+
+        if !line.is_empty() && 4 * depth + line.len() + chunk.len() > LINE_WIDTH {
+            flush(&mut line, depth);
+        }
+        line.push_str(&chunk);
+    }
+    flush(&mut line, depth);
+
+    formatted
+}
+
+/// List the ops of an IR, one per line with loop bodies indented.
+fn dump_ir(ir: &IR) -> String {
+    let width = ir.tokens.len().saturating_sub(1).to_string().len();
+    let mut dump = String::new();
+    let mut depth = 0;
+
+    for (idx, &op) in ir.tokens.iter().enumerate() {
+        if op == Op::LoopEnd {
+            depth -= 1;
+        }
+        let indent = "    ".repeat(depth);
+        let line = match op {
+            Op::LoopStart | Op::LoopEnd => {
+                format!("{idx:>width$}  {indent}{op:?} -> {}\n", ir.jump_table[idx])
+            }
+            _ => format!("{idx:>width$}  {indent}{op:?}\n"),
+        };
+        dump.push_str(&line);
+        if op == Op::LoopStart {
+            depth += 1;
+        }
+    }
+
+    dump
+}
+
+/// Compile an unoptimized IR back to Brainfuck, with comments removed.
+fn compile_bf(ir: &IR) -> String {
+    let mut code: String = ir.tokens.iter().filter_map(|op| op.into_char()).collect();
+    code.push('\n');
+    code
 }
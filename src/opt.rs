@@ -0,0 +1,84 @@
+// The optimization pass manager.
+
+use crate::ir::IR;
+
+/// How aggressively to optimize an [`IR`].
+///
+/// Above `O0` runs of moves are folded into one, so the tape is only checked
+/// where the pointer ends up and not at the cells it passes on the way: with
+/// [`TapePolicy::Error`](crate::vm::TapePolicy::Error), `<<>>` fails at `O0`
+/// but does nothing at the other levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// Keep the program exactly as written, one op per instruction.
+    O0,
+    /// Fold runs of `+-<>` into counted ops.
+    O1,
+    /// Fold runs, recognize loop idioms and address cells by offset.
+    #[default]
+    O2,
+}
+
+/// A configurable pipeline of optimization passes.
+///
+/// Every pass can be toggled on its own, which makes it possible to
+/// bisect a miscompilation down to a single pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Optimizer {
+    /// Run [`IR::fold_runs`].
+    pub fold_runs: bool,
+    /// Run [`IR::recognize_clear_loops`].
+    pub clear_loops: bool,
+    /// Run [`IR::recognize_mul_loops`].
+    pub mul_loops: bool,
+    /// Run [`IR::recognize_scan_loops`].
+    pub scan_loops: bool,
+    /// Run [`IR::defer_moves`].
+    pub defer_moves: bool,
+}
+
+impl Optimizer {
+    /// Create an optimizer with the passes enabled by an [`OptLevel`].
+    pub const fn new(level: OptLevel) -> Self {
+        let idioms = matches!(level, OptLevel::O2);
+        Self {
+            fold_runs: !matches!(level, OptLevel::O0),
+            clear_loops: idioms,
+            mul_loops: idioms,
+            scan_loops: idioms,
+            defer_moves: idioms,
+        }
+    }
+
+    /// Run the enabled passes over an IR, in pipeline order.
+    pub fn run(&self, ir: &mut IR) {
+        if self.fold_runs {
+            ir.fold_runs();
+        }
+        if self.clear_loops {
+            ir.recognize_clear_loops();
+        }
+        if self.mul_loops {
+            ir.recognize_mul_loops();
+        }
+        if self.scan_loops {
+            ir.recognize_scan_loops();
+        }
+        if self.defer_moves {
+            ir.defer_moves();
+        }
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new(OptLevel::default())
+    }
+}
+
+impl IR {
+    /// Optimize the IR with the passes enabled by an [`OptLevel`].
+    pub fn optimize(&mut self, level: OptLevel) {
+        Optimizer::new(level).run(self);
+    }
+}
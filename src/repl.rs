@@ -0,0 +1,176 @@
+// An interactive REPL that runs lines of code against one tape.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Write},
+    mem,
+    process::ExitCode,
+    str::FromStr,
+    task::Poll,
+};
+
+use bfc::{
+    Cell, IR, Input, OutputEncoding, RunStatus, RuntimeError, VM, VMOptions,
+    ir::{ParseError, ParseErrorKind},
+};
+
+use crate::{cli::Args, dump_to_stderr};
+
+/// How many cells to show on either side of the pointer.
+const NEIGHBOURS: u32 = 4;
+
+/// Input typed in by the user while a program waits for it.
+#[derive(Default)]
+pub struct LineInput {
+    buffer: VecDeque<u8>,
+    /// Whether stdin has run out.
+    eof: bool,
+}
+
+impl LineInput {
+    /// Input that's known upfront, without asking for more.
+    pub fn ended(input: &str) -> Self {
+        Self {
+            buffer: input.bytes().collect(),
+            eof: true,
+        }
+    }
+}
+
+impl Input for LineInput {
+    fn read(&mut self) -> Poll<Option<u8>> {
+        match self.buffer.pop_front() {
+            Some(byte) => Poll::Ready(Some(byte)),
+            None if self.eof => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Run the REPL on stdin and stdout with a tape of `C` cells, starting
+/// with the `preload`ed program if there is one.
+pub fn repl<C: Cell>(args: &Args, preload: Option<String>) -> io::Result<ExitCode> {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+
+    let (memory_buffer_size, tape_policy) = args.tape();
+    let options = VMOptions {
+        memory_buffer_size,
+        tape_policy,
+        output_encoding: OutputEncoding::Truncate,
+        eof_behavior: args.eof_behavior,
+        output: Vec::new(),
+        input: LineInput::default(),
+        jit: args.jit,
+    };
+    let mut vm = VM::<_, _, C>::from_ir(IR::from_str("").unwrap(), options);
+    dump_to_stderr(&mut vm);
+
+    let mut source = String::new();
+    let mut preload = preload;
+    loop {
+        if let Some(program) = preload.take() {
+            source = program;
+        } else {
+            let prompt = if source.is_empty() { "bf> " } else { "... " };
+            write!(stdout, "{prompt}")?;
+            stdout.flush()?;
+            if stdin.read_line(&mut source)? == 0 {
+                // Leave the shell's prompt on a line of its own.
+                writeln!(stdout)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+        }
+
+        // Keep reading lines until every loop is closed.
+        let mut ir = match args.dialect.parse(&source) {
+            Ok(ir) => ir,
+            Err(ParseError {
+                kind: ParseErrorKind::UnclosedLoop,
+                ..
+            }) => continue,
+            Err(err) => {
+                eprintln!("{}\n", err.render(&source));
+                source.clear();
+                continue;
+            }
+        };
+        // Queue up the input given after a `!`.
+        if let (_, Some(input)) = args.dialect.split_input(&source) {
+            vm.input_mut().buffer.extend(input.bytes());
+        }
+
+        ir.optimize(args.opt_level);
+        vm.load(ir);
+        let result = execute(&mut vm, &mut stdin, &mut stdout)?;
+        if let Err(err) = result {
+            eprintln!("{}", err.render(vm.ir(), &source));
+        }
+        source.clear();
+        show_tape(&vm, &mut stdout)?;
+    }
+}
+
+/// Run the loaded program to completion, reading input from `stdin` when
+/// it waits for it.
+fn execute<C: Cell>(
+    vm: &mut VM<LineInput, Vec<u8>, C>,
+    stdin: &mut impl BufRead,
+    stdout: &mut impl Write,
+) -> io::Result<Result<RunStatus, RuntimeError>> {
+    let mut at_line_start = true;
+    let result = loop {
+        let status = vm.run_for(1 << 16);
+        drain_output(vm, stdout, &mut at_line_start)?;
+
+        match status {
+            Ok(RunStatus::OutOfFuel) => {}
+            Ok(RunStatus::AwaitingInput) => {
+                stdout.flush()?;
+                read_input(vm, stdin)?;
+            }
+            result => break result,
+        }
+    };
+
+    // Keep the tape off the end of the program's output.
+    if !at_line_start {
+        writeln!(stdout)?;
+    }
+
+    Ok(result)
+}
+
+/// Write out and clear the program's output so far, keeping track of
+/// whether it ended with a complete line.
+pub fn drain_output<C: Cell>(
+    vm: &mut VM<LineInput, Vec<u8>, C>,
+    stdout: &mut impl Write,
+    at_line_start: &mut bool,
+) -> io::Result<()> {
+    let output = mem::take(vm.output_mut());
+    if let Some(&last) = output.last() {
+        *at_line_start = last == b'\n';
+    }
+    stdout.write_all(&output)
+}
+
+/// Read a line from `stdin` for the program to use as input.
+pub fn read_input<C: Cell>(
+    vm: &mut VM<LineInput, Vec<u8>, C>,
+    stdin: &mut impl BufRead,
+) -> io::Result<()> {
+    let mut line = String::new();
+    let input = vm.input_mut();
+    input.eof = stdin.read_line(&mut line)? == 0;
+    input.buffer.extend(line.bytes());
+    Ok(())
+}
+
+/// Show the pointer and the cells around it.
+pub fn show_tape<C: Cell>(
+    vm: &VM<LineInput, Vec<u8>, C>,
+    stdout: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(stdout, "{}", vm.window(NEIGHBOURS))
+}
@@ -1,89 +1,318 @@
 // The Brainfuck VM.
 
-use core::str::FromStr;
+use core::{
+    fmt,
+    str::FromStr,
+    task::{Poll, ready},
+};
 
-use alloc::{boxed::Box, vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 
-use crate::ir::{IR, Op, ParseError};
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+use crate::jit::{Exit, Jit, State, Trampolines};
+use crate::{
+    cell::{Cell, OutputEncoding},
+    io::{Input, Output},
+    ir::{IR, Op, ParseError, Report},
+};
+
+/// What to do when the program moves off either end of the tape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapePolicy {
+    /// Stop with a [`RuntimeError`].
+    Error,
+    /// Wrap around to the other end of the tape.
+    Wrap,
+    /// Grow the tape to the right on demand, moving off the left end is
+    /// still an error.
+    Grow {
+        /// The size in cells the tape may never grow beyond, moving past
+        /// it is an error.
+        max_size: Option<u32>,
+    },
+    /// Grow the tape on demand in both directions, so the program can
+    /// move left of the starting cell.
+    Bidirectional {
+        /// The size in cells the tape may never grow beyond, moving past
+        /// it is an error.
+        max_size: Option<u32>,
+    },
+}
+
+/// What the `,` instruction does once the input has run out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofBehavior {
+    /// Leave the cell unchanged.
+    #[default]
+    Unchanged,
+    /// Store 0 in the cell.
+    Zero,
+    /// Store -1 in the cell, i.e. its maximum value, 255 for byte cells.
+    MinusOne,
+}
+
+/// Why [`VM::run_for`] stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// The program has finished executing.
+    Halted,
+    /// The program used up its fuel.
+    OutOfFuel,
+    /// The program is waiting at a `,` for input that isn't available yet.
+    AwaitingInput,
+}
+
+/// Errors that can occur while running a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// The program moved left of the first cell.
+    TapeUnderflow {
+        /// The index of the Op that moved the pointer.
+        op_idx: u32,
+        /// The cell the pointer would have moved to, relative to the
+        /// starting cell.
+        ptr: i64,
+    },
+    /// The program moved right of the last cell.
+    TapeOverflow {
+        /// The index of the Op that moved the pointer.
+        op_idx: u32,
+        /// The cell the pointer would have moved to, relative to the
+        /// starting cell.
+        ptr: i64,
+    },
+}
+
+impl RuntimeError {
+    /// Render the error with the source of the Op it happened at, like
+    /// [`ParseError::render`]. `ir` must be the IR that was running and
+    /// `source` the string it was parsed from.
+    pub fn render<'a>(&'a self, ir: &IR, source: &'a str) -> Report<'a> {
+        let (Self::TapeUnderflow { op_idx, .. } | Self::TapeOverflow { op_idx, .. }) = *self;
+        Report::new(self, ir.spans[op_idx as usize], source)
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TapeUnderflow { ptr, .. } => {
+                write!(f, "tape underflow: pointer moved to cell {ptr}")
+            }
+            Self::TapeOverflow { ptr, .. } => {
+                write!(f, "tape overflow: pointer moved to cell {ptr}")
+            }
+        }
+    }
+}
+
+/// The cells around the pointer, as handed to the debug hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapeWindow<'a, C> {
+    /// The cell the pointer is on, relative to the starting cell.
+    pub pointer: i64,
+    /// The first cell in the window, relative to the starting cell.
+    pub start: i64,
+    /// The cells in the window, cut short at the ends of the tape.
+    pub cells: &'a [C],
+}
+
+impl<C: Cell> fmt::Display for TapeWindow<'_, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = self.start + self.cells.len() as i64 - 1;
+        write!(f, "ptr {} | cells {}..={end}:", self.pointer, self.start)?;
+        for (cell, value) in (self.start..).zip(self.cells) {
+            if cell == self.pointer {
+                write!(f, " [{}]", value.to_u64())?;
+            } else {
+                write!(f, " {}", value.to_u64())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A hook run by [`Op::Debug`] with the index of the Op and the cells
+/// around the pointer.
+type DebugHook<C> = Box<dyn FnMut(u32, TapeWindow<'_, C>) + Send>;
 
 /// Options for the VM.
-pub struct VMOptions<'a> {
+pub struct VMOptions<I, O> {
     /// The size of the memory buffer in bytes.
+    ///
+    /// With [`TapePolicy::Grow`] and [`TapePolicy::Bidirectional`] this is
+    /// only the initial size. At least one cell is always allocated.
     pub memory_buffer_size: u32,
-    /// The output function to use for the `.` instruction.
-    pub out_fn: &'a mut dyn FnMut(u8),
-    /// The input function to use for the `,` instruction.
-    pub in_fn: &'a mut dyn FnMut() -> u8,
+    /// What to do when the program moves off the tape.
+    pub tape_policy: TapePolicy,
+    /// How cells are turned into output bytes.
+    pub output_encoding: OutputEncoding,
+    /// What the `,` instruction does at the end of the input.
+    pub eof_behavior: EofBehavior,
+    /// The output to use for the `.` instruction.
+    pub output: O,
+    /// The input to use for the `,` instruction.
+    pub input: I,
+    /// Compile the program to machine code and run that, going back to the
+    /// interpreter for Ops it can't handle, like growing the tape.
+    ///
+    /// Only x86-64 Linux is supported, elsewhere the program is always
+    /// interpreted. The compiled code can't unwind, so a panic in the
+    /// input or output aborts the process instead.
+    pub jit: bool,
 }
 
-/// The Brainfuck VM.
-pub struct VM<'a> {
+/// The Brainfuck VM, generic over its [`Input`], [`Output`] and the
+/// [`Cell`] type of its tape.
+pub struct VM<I, O, C: Cell = u8> {
     /// The IR to execute.
     ir: IR,
     /// The memory buffer for the program.
-    memory_buffer: Box<[u8]>,
+    memory_buffer: Vec<C>,
+    /// What to do when the program moves off the tape.
+    tape_policy: TapePolicy,
+    /// How cells are turned into output bytes.
+    output_encoding: OutputEncoding,
+    /// What the `,` instruction does at the end of the input.
+    eof_behavior: EofBehavior,
     /// A pointer to the current cell in the memory buffer.
     memory_buffer_ptr: u32,
+    /// The index in the memory buffer of the starting cell, which moves
+    /// right as the tape grows to the left.
+    origin: u32,
     /// The index of the next Op to execute.
     current_token_idx: u32,
-    /// The output function.
-    out_fn: &'a mut dyn FnMut(u8),
-    /// The input function.
-    in_fn: &'a mut dyn FnMut() -> u8,
+    /// The output.
+    output: O,
+    /// The input.
+    input: I,
+    /// Whether the last step stopped at a `,` for lack of input.
+    awaiting_input: bool,
+    /// The hook to run for [`Op::Debug`], and how many cells on either
+    /// side of the pointer it gets.
+    debug_hook: Option<(u32, DebugHook<C>)>,
+    /// The compiled program, if the JIT is enabled.
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    jit: Option<Jit>,
 }
 
-impl<'a> VM<'a> {
+impl<I: Input, O: Output, C: Cell> VM<I, O, C> {
     /// Create a new VM from a source string.
-    pub fn new(source: &str, options: VMOptions<'a>) -> Result<Self, ParseError> {
+    pub fn new(source: &str, options: VMOptions<I, O>) -> Result<Self, ParseError> {
         let ir = IR::from_str(source)?;
         Ok(Self::from_ir(ir, options))
     }
 
     /// Create a new VM from an IR.
-    pub fn from_ir(ir: IR, options: VMOptions<'a>) -> Self {
+    pub fn from_ir(ir: IR, options: VMOptions<I, O>) -> Self {
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        let jit = options.jit.then(|| Self::compile(&ir)).flatten();
         Self {
             ir,
-            memory_buffer: vec![0; options.memory_buffer_size as usize].into_boxed_slice(),
+            // The pointer always needs a cell to be on.
+            memory_buffer: vec![C::default(); options.memory_buffer_size.max(1) as usize],
+            tape_policy: options.tape_policy,
+            output_encoding: options.output_encoding,
+            eof_behavior: options.eof_behavior,
             memory_buffer_ptr: 0,
+            origin: 0,
             current_token_idx: 0,
-            out_fn: options.out_fn,
-            in_fn: options.in_fn,
+            output: options.output,
+            input: options.input,
+            awaiting_input: false,
+            debug_hook: None,
+            #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+            jit,
         }
     }
 
     /// Execute a single step of the VM.
     ///
-    /// Returns `false` if the program has finished executing.
-    pub fn step(&mut self) -> bool {
+    /// Returns `false` if the program has finished executing. On error the
+    /// VM stays at the Op that failed.
+    pub fn step(&mut self) -> Result<bool, RuntimeError> {
         // Check if we've reached the end of the program.
         if self.current_token_idx as usize >= self.ir.tokens.len() {
-            return false;
+            return Ok(false);
         }
 
         let current_token = self.ir.tokens[self.current_token_idx as usize];
+        // The pointer is always kept on the tape, only offsets need checks.
         let heap_ptr = self.memory_buffer_ptr as usize;
 
         // Execute the current token.
         match current_token {
-            Op::IncPtr => self.memory_buffer_ptr += 1,
-            Op::DecPtr => self.memory_buffer_ptr -= 1,
+            Op::IncPtr => self.move_ptr(1)?,
+            Op::DecPtr => self.move_ptr(-1)?,
             Op::IncByte => {
-                self.memory_buffer[heap_ptr] = self.memory_buffer[heap_ptr].wrapping_add(1)
+                self.memory_buffer[heap_ptr] =
+                    self.memory_buffer[heap_ptr].wrapping_add(C::from_i32(1))
             }
             Op::DecByte => {
-                self.memory_buffer[heap_ptr] = self.memory_buffer[heap_ptr].wrapping_sub(1)
+                self.memory_buffer[heap_ptr] =
+                    self.memory_buffer[heap_ptr].wrapping_sub(C::from_i32(1))
+            }
+            Op::Add { offset, amount } => {
+                let cell = self.cell_idx(offset)?;
+                self.memory_buffer[cell] =
+                    self.memory_buffer[cell].wrapping_add(C::from_i32(amount))
+            }
+            Op::Move(amount) => self.move_ptr(amount)?,
+            Op::Set { offset, value } => {
+                let cell = self.cell_idx(offset)?;
+                self.memory_buffer[cell] = C::from_i32(value)
+            }
+            Op::MulAdd { offset, factor } => {
+                // Skip zero cells, so targets are only touched when the
+                // loop this op came from would have run.
+                let value = self.memory_buffer[heap_ptr];
+                if value != C::default() {
+                    let target = self.cell_idx(offset)?;
+                    self.memory_buffer[target] = self.memory_buffer[target]
+                        .wrapping_add(value.wrapping_mul(C::from_i32(factor)));
+                }
+            }
+            Op::Scan { stride } => {
+                let Some(zero_ptr) = self.scan(heap_ptr, stride)? else {
+                    // No zero cell is reachable, so the loop never ends.
+                    return Ok(true);
+                };
+                self.memory_buffer_ptr = zero_ptr as u32;
+            }
+            Op::OutByte => self.write_cell(heap_ptr),
+            Op::InByte => {
+                if self.read_cell(heap_ptr).is_pending() {
+                    // Retry the same Op once input is available.
+                    return Ok(true);
+                }
+            }
+            Op::Out { offset } => {
+                let cell = self.cell_idx(offset)?;
+                self.write_cell(cell)
+            }
+            Op::In { offset } => {
+                let cell = self.cell_idx(offset)?;
+                if self.read_cell(cell).is_pending() {
+                    return Ok(true);
+                }
+            }
+            Op::Debug => {
+                if let Some((radius, hook)) = &mut self.debug_hook {
+                    // Line the snapshot up with the output so far.
+                    self.output.flush();
+                    let window = window(&self.memory_buffer, heap_ptr, self.origin, *radius);
+                    hook(self.current_token_idx, window);
+                }
             }
-            Op::OutByte => (self.out_fn)(self.memory_buffer[heap_ptr]),
-            Op::InByte => self.memory_buffer[heap_ptr] = (self.in_fn)(),
             Op::LoopStart => {
                 // If the current cell is 0, jump to the matching `]`.
-                if self.memory_buffer[heap_ptr] == 0 {
+                if self.memory_buffer[heap_ptr] == C::default() {
                     self.current_token_idx = self.ir.jump_table[self.current_token_idx as usize];
                 }
             }
             Op::LoopEnd => {
                 // If the current cell is not 0, jump to the matching `[`.
-                if self.memory_buffer[heap_ptr] != 0 {
+                if self.memory_buffer[heap_ptr] != C::default() {
                     self.current_token_idx = self.ir.jump_table[self.current_token_idx as usize];
                 }
             }
@@ -92,11 +321,373 @@ impl<'a> VM<'a> {
         self.current_token_idx += 1;
 
         // Return true if there are more tokens to execute.
-        (self.current_token_idx as usize) < self.ir.tokens.len()
+        Ok((self.current_token_idx as usize) < self.ir.tokens.len())
+    }
+
+    /// Run the VM until the program has finished executing, or is
+    /// waiting for input.
+    pub fn run(&mut self) -> Result<RunStatus, RuntimeError> {
+        loop {
+            if let status @ (RunStatus::Halted | RunStatus::AwaitingInput) =
+                self.run_for(u64::MAX)?
+            {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Run the VM for at most `fuel` steps.
+    ///
+    /// The VM can be resumed with another call after it stops, picking up
+    /// at the same Op.
+    pub fn run_for(&mut self, fuel: u64) -> Result<RunStatus, RuntimeError> {
+        let mut fuel = fuel;
+        while fuel > 0 {
+            #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+            if let Some(status) = self.run_jit(&mut fuel) {
+                return Ok(status);
+            }
+            if fuel == 0 {
+                break;
+            }
+
+            fuel -= 1;
+            if !self.step()? {
+                return Ok(RunStatus::Halted);
+            }
+            if self.awaiting_input {
+                return Ok(RunStatus::AwaitingInput);
+            }
+        }
+
+        if self.current_token_idx as usize >= self.ir.tokens.len() {
+            Ok(RunStatus::Halted)
+        } else {
+            Ok(RunStatus::OutOfFuel)
+        }
+    }
+
+    /// Replace the program with a new IR and start executing it from its
+    /// first Op, keeping the tape and pointer as they are.
+    pub fn load(&mut self, ir: IR) {
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        if self.jit.is_some() {
+            self.jit = Self::compile(&ir);
+        }
+        self.ir = ir;
+        self.current_token_idx = 0;
+        self.awaiting_input = false;
+    }
+
+    /// The cell the pointer is on, relative to the starting cell.
+    pub fn pointer(&self) -> i64 {
+        self.memory_buffer_ptr as i64 - self.origin as i64
+    }
+
+    /// The value of a cell, numbered relative to the starting cell.
+    ///
+    /// Cells that aren't on the tape yet are zero.
+    pub fn cell(&self, cell: i64) -> C {
+        usize::try_from(cell + self.origin as i64)
+            .ok()
+            .and_then(|idx| self.memory_buffer.get(idx).copied())
+            .unwrap_or_default()
+    }
+
+    /// The cells up to `radius` cells on either side of the pointer.
+    pub fn window(&self, radius: u32) -> TapeWindow<'_, C> {
+        window(
+            &self.memory_buffer,
+            self.memory_buffer_ptr as usize,
+            self.origin,
+            radius,
+        )
+    }
+
+    /// Set the hook to run for [`Op::Debug`], which gets the index of the
+    /// Op and the cells up to `radius` cells on either side of the pointer.
+    pub fn set_debug_hook(
+        &mut self,
+        radius: u32,
+        hook: impl FnMut(u32, TapeWindow<'_, C>) + Send + 'static,
+    ) {
+        self.debug_hook = Some((radius, Box::new(hook)));
+    }
+
+    /// The IR being executed.
+    pub fn ir(&self) -> &IR {
+        &self.ir
+    }
+
+    /// The memory buffer, i.e. the part of the tape allocated so far.
+    pub fn memory_buffer(&self) -> &[C] {
+        &self.memory_buffer
+    }
+
+    /// The index in the memory buffer of the cell the pointer is on.
+    pub fn memory_buffer_ptr(&self) -> u32 {
+        self.memory_buffer_ptr
+    }
+
+    /// The index of the next Op to execute.
+    pub fn current_token_idx(&self) -> u32 {
+        self.current_token_idx
+    }
+
+    /// Get a reference to the output.
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    /// Get a mutable reference to the output, e.g. to drain it.
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
+    }
+
+    /// Get a mutable reference to the input, e.g. to feed it more bytes.
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    /// Consume the VM, returning its input and output.
+    pub fn into_io(self) -> (I, O) {
+        (self.input, self.output)
+    }
+
+    /// Dump the tape as `(cell, value)` pairs.
+    ///
+    /// Cells are numbered relative to the starting cell, so cells left of
+    /// it have negative indices.
+    pub fn memory_dump(&self) -> impl Iterator<Item = (i64, C)> + '_ {
+        let origin = self.origin as i64;
+        (self.memory_buffer.iter().enumerate())
+            .map(move |(idx, &value)| (idx as i64 - origin, value))
+    }
+
+    /// Output the cell at `idx` in the memory buffer.
+    fn write_cell(&mut self, idx: usize) {
+        self.write_value(self.memory_buffer[idx]);
+    }
+
+    /// Output the value of a cell.
+    fn write_value(&mut self, value: C) {
+        let mut buffer = [0; 4];
+        for &byte in self.output_encoding.encode(value, &mut buffer) {
+            self.output.write(byte);
+        }
+    }
+
+    /// Read input into the cell at `idx` in the memory buffer.
+    fn read_cell(&mut self, idx: usize) -> Poll<()> {
+        self.memory_buffer[idx] = ready!(self.read_value(self.memory_buffer[idx]));
+        Poll::Ready(())
+    }
+
+    /// Read input for a cell holding `value`, returning its new value.
+    fn read_value(&mut self, value: C) -> Poll<C> {
+        self.output.flush();
+        let Poll::Ready(input) = self.input.read() else {
+            self.awaiting_input = true;
+            return Poll::Pending;
+        };
+        self.awaiting_input = false;
+
+        Poll::Ready(match (input, self.eof_behavior) {
+            (Some(byte), _) => C::from_byte(byte),
+            (None, EofBehavior::Unchanged) => value,
+            (None, EofBehavior::Zero) => C::default(),
+            (None, EofBehavior::MinusOne) => C::from_i32(-1),
+        })
+    }
+
+    /// Compile a program for the JIT, with the trampolines for this VM.
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn compile(ir: &IR) -> Option<Jit> {
+        let trampolines = Trampolines {
+            output: jit_output::<I, O, C>,
+            input: jit_input::<I, O, C>,
+        };
+        Jit::compile::<C>(ir, &trampolines)
+    }
+
+    /// Run the compiled program if there is one and it can start at the
+    /// current Op, using up `fuel`.
+    ///
+    /// Returns the status if the program halted or is waiting for input,
+    /// and `None` if the interpreter should carry on.
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn run_jit(&mut self, fuel: &mut u64) -> Option<RunStatus> {
+        let jit = self.jit.take()?;
+        if !jit.is_entry(self.current_token_idx) {
+            self.jit = Some(jit);
+            return None;
+        }
+
+        let tape = self.memory_buffer.as_mut_ptr();
+        let len = self.memory_buffer.len();
+        let mut state = State {
+            vm: (self as *mut Self).cast(),
+            ptr: self.memory_buffer_ptr as u64,
+            fuel: *fuel,
+            idx: 0,
+        };
+        // SAFETY: The Op is an entry, the tape is the memory buffer, which
+        // always has a cell for the pointer and which the trampolines don't
+        // touch, and they're for this VM.
+        let exit = unsafe { jit.run(self.current_token_idx, &mut state, tape, len) };
+        self.jit = Some(jit);
+
+        self.memory_buffer_ptr = state.ptr as u32;
+        self.current_token_idx = state.idx as u32;
+        *fuel = state.fuel;
+        match exit {
+            Exit::Halted => Some(RunStatus::Halted),
+            Exit::AwaitingInput => Some(RunStatus::AwaitingInput),
+            Exit::Interpret => None,
+        }
+    }
+
+    /// Move the pointer by `amount` cells.
+    fn move_ptr(&mut self, amount: i32) -> Result<(), RuntimeError> {
+        self.memory_buffer_ptr = self.cell_idx(amount)? as u32;
+        Ok(())
+    }
+
+    /// Get the index of the cell `offset` cells away from the pointer.
+    fn cell_idx(&mut self, offset: i32) -> Result<usize, RuntimeError> {
+        self.resolve(self.memory_buffer_ptr as i64 + offset as i64)
+    }
+
+    /// Map a cell position onto the memory buffer, applying the tape
+    /// policy if it's off the tape.
+    fn resolve(&mut self, pos: i64) -> Result<usize, RuntimeError> {
+        let len = self.memory_buffer.len() as i64;
+        if (0..len).contains(&pos) {
+            return Ok(pos as usize);
+        }
+
+        match self.tape_policy {
+            TapePolicy::Wrap if len > 0 => Ok(pos.rem_euclid(len) as usize),
+            TapePolicy::Grow { max_size } | TapePolicy::Bidirectional { max_size }
+                if pos >= len && pos < grow_limit(max_size) =>
+            {
+                // Grow at least geometrically, so runs of `>` stay cheap.
+                let new_len = (pos + 1).max((len * 2).min(grow_limit(max_size)));
+                self.memory_buffer.resize(new_len as usize, C::default());
+                Ok(pos as usize)
+            }
+            TapePolicy::Bidirectional { max_size }
+                if pos < 0 && len - pos <= grow_limit(max_size) =>
+            {
+                // Prepend at least as many cells as there already are, so
+                // runs of `<` don't keep shifting the whole tape.
+                let added = (-pos).max(len.min(grow_limit(max_size) - len));
+                let mut memory_buffer = vec![C::default(); added as usize];
+                memory_buffer.extend_from_slice(&self.memory_buffer);
+                self.memory_buffer = memory_buffer;
+                self.origin += added as u32;
+                self.memory_buffer_ptr += added as u32;
+                Ok((pos + added) as usize)
+            }
+            _ => {
+                let op_idx = self.current_token_idx;
+                let pos = pos - self.origin as i64;
+                Err(if pos < 0 {
+                    RuntimeError::TapeUnderflow { op_idx, ptr: pos }
+                } else {
+                    RuntimeError::TapeOverflow { op_idx, ptr: pos }
+                })
+            }
+        }
+    }
+
+    /// Find the first zero cell reachable from `start` in steps of
+    /// `stride`, starting with `start` itself.
+    ///
+    /// Returns `None` if the tape wraps and no zero cell is reachable.
+    fn scan(&mut self, start: usize, stride: i32) -> Result<Option<usize>, RuntimeError> {
+        let mut ptr = start;
+
+        // Every pass over a wrapping tape visits at least `len / stride`
+        // new cells, so `stride + 1` passes cover every reachable cell.
+        for _ in 0..=stride.unsigned_abs() {
+            // Search the tape, finding the first position past its end.
+            let edge = match stride {
+                1 => match C::find_zero(&self.memory_buffer[ptr..]) {
+                    Some(idx) => return Ok(Some(ptr + idx)),
+                    None => self.memory_buffer.len() as i64,
+                },
+                -1 => match C::rfind_zero(&self.memory_buffer[..=ptr]) {
+                    Some(idx) => return Ok(Some(idx)),
+                    None => -1,
+                },
+                _ => {
+                    let mut pos = ptr as i64;
+                    while let Some(&cell) = usize::try_from(pos)
+                        .ok()
+                        .and_then(|pos| self.memory_buffer.get(pos))
+                    {
+                        if cell == C::default() {
+                            return Ok(Some(pos as usize));
+                        }
+                        pos += stride as i64;
+                    }
+                    pos
+                }
+            };
+
+            // Carry on from the other end, or the newly grown cells.
+            ptr = self.resolve(edge)?;
+        }
+
+        Ok(None)
     }
+}
+
+/// Output the cell at `cell` for compiled code.
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+unsafe extern "sysv64" fn jit_output<I: Input, O: Output, C: Cell>(vm: *mut (), cell: *const ()) {
+    let vm = vm.cast::<VM<I, O, C>>();
+    // SAFETY: Compiled code passes the VM it runs for and a cell on its
+    // tape.
+    unsafe { (*vm).write_value(*cell.cast::<C>()) }
+}
+
+/// Read input into the cell at `cell` for compiled code, returning 1 if
+/// it's still waiting for input and 0 otherwise.
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+unsafe extern "sysv64" fn jit_input<I: Input, O: Output, C: Cell>(
+    vm: *mut (),
+    cell: *mut (),
+) -> u32 {
+    let (vm, cell) = (vm.cast::<VM<I, O, C>>(), cell.cast::<C>());
+    // SAFETY: As for `jit_output`.
+    unsafe {
+        match (*vm).read_value(*cell) {
+            Poll::Ready(value) => {
+                *cell = value;
+                0
+            }
+            Poll::Pending => 1,
+        }
+    }
+}
+
+/// The number of cells a growing tape may reach.
+fn grow_limit(max_size: Option<u32>) -> i64 {
+    // The pointer is a `u32`, so that's as far as it can go.
+    max_size.map_or(u32::MAX as i64 + 1, i64::from)
+}
 
-    /// Run the VM until the program has finished executing.
-    pub fn run(&mut self) {
-        while self.step() {}
+/// The cells up to `radius` cells on either side of `ptr`.
+fn window<C>(memory_buffer: &[C], ptr: usize, origin: u32, radius: u32) -> TapeWindow<'_, C> {
+    let start = ptr.saturating_sub(radius as usize);
+    let end = ptr
+        .saturating_add(radius as usize + 1)
+        .min(memory_buffer.len());
+    TapeWindow {
+        pointer: ptr as i64 - origin as i64,
+        start: start as i64 - origin as i64,
+        cells: &memory_buffer[start..end],
     }
 }
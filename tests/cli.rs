@@ -0,0 +1,393 @@
+// Tests for the `bfc` binary.
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Write a program to a temporary file, named after the test using it.
+fn program_file(name: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("bfc-{}-{name}.b", std::process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+/// The first `len` bytes a command writes to stdout, for programs that never
+/// halt. Stdin is closed, so `,` sees EOF.
+fn first_output(command: &mut Command, len: u64) -> Vec<u8> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut output = Vec::new();
+    let stdout = child.stdout.take().unwrap();
+    stdout.take(len).read_to_end(&mut output).unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    output
+}
+
+/// Every program in the `examples/` directory.
+fn examples() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut examples: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    examples.sort();
+    examples
+}
+
+#[test]
+fn test_byte_exact_output() {
+    // Output every byte value once, in order.
+    let path = program_file("all-bytes", &".+".repeat(256));
+    let expected: Vec<u8> = (0..=255).collect();
+
+    for opt_level in ["-O0", "-O2"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_bfc"))
+            .arg(opt_level)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, expected);
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_subcommands() {
+    let bfc = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_bfc"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // Inline programs, with the run command being the default.
+    let output = bfc(&["run", "-e", "++++++++[>++++++++<-]>+."]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+    assert_eq!(bfc(&["-e", "+++[>+++<-]>."]).stdout, [9]);
+
+    // Wider cells and a fixed tape.
+    assert_eq!(bfc(&["--cell-width", "16", "-e", "-."]).stdout, [255]);
+    let output = bfc(&["--tape-size=2", "-e", "+\n>>"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(" --> 2:1"));
+
+    let output = bfc(&["check", "-e", "+[>+"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unclosed loop"));
+
+    let output = bfc(&["fmt", "-e", "a+[b-[-]>[<]]"]);
+    assert_eq!(output.stdout, b"+\n[\n    -[-]>[<]\n]\n");
+
+    let output = bfc(&["compile", "--target", "bf", "-e", "+ comment [-]"]);
+    assert_eq!(output.stdout, b"+[-]\n");
+
+    // Dialect extensions.
+    let output = bfc(&["--debug-dump", "--input-separator", "-e", "+#,.!A"]);
+    assert_eq!(output.stdout, b"A");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("# at op 1: ptr 0 | cells 0..=8: [1] 0")
+    );
+
+    // Usage errors.
+    assert_eq!(bfc(&["run"]).status.code(), Some(2));
+    assert_eq!(bfc(&["-O3", "-e", "+"]).status.code(), Some(2));
+    assert_eq!(bfc(&["--eof", "maybe", "-e", ","]).status.code(), Some(2));
+    // Compiled programs can't take input after `!`.
+    for target in ["c", "rust", "x86_64", "elf"] {
+        let output = bfc(&[
+            "compile",
+            "--input-separator",
+            "--target",
+            target,
+            "-e",
+            ",.!A",
+        ]);
+        assert_eq!(output.status.code(), Some(2), "{target}");
+    }
+}
+
+#[test]
+fn test_repl() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .args(["repl", "-e", "+++"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The tape is kept between lines, and an unclosed loop continues on
+    // the next line.
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"[>++\n<-]>.\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "ptr 0 | cells 0..=4: [3] 0 0 0 0\n\
+         bf> ... \x06\n\
+         ptr 1 | cells 0..=5: 0 [6] 0 0 0 0\n\
+         bf> \n"
+    );
+}
+
+#[test]
+fn test_debug() {
+    let path = program_file("debug", "+++[>++<-]\n\t>[-]");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .args(["debug", "-O0"])
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"break 2:4 if == 6\ncontinue\nnext\nwatch 0\ncontinue\nquit\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Breakpoint 0 at op 12."));
+    // The caret keeps the tab, so it lines up under the op.
+    assert!(
+        stdout.contains("Hit breakpoint 0.\nop 12 at 2:4: DecByte\n  |\n2 | \t>[-]\n  | \t  ^\n")
+    );
+    assert!(stdout.contains("ptr 1 | cells 0..=5: 0 [6] 0 0 0 0\n"));
+    // The rest of the program clears the cell without touching the watched
+    // one.
+    assert!(stdout.contains("ptr 1 | cells 0..=5: 0 [0] 0 0 0 0\n"));
+    assert!(stdout.contains("The program has finished."));
+}
+
+/// An external compiler that turns bfc's output into a binary.
+struct Compiler {
+    /// The command to run.
+    name: &'static str,
+    /// The extension of the files it compiles.
+    extension: &'static str,
+    /// The flags to run it with.
+    flags: &'static [&'static str],
+}
+
+/// Whether `compiler` is installed, or there's no need for one.
+fn installed(compiler: Option<&Compiler>) -> bool {
+    let Some(compiler) = compiler else {
+        return true;
+    };
+    let found = Command::new(compiler.name)
+        .arg("--version")
+        .output()
+        .is_ok();
+    if !found {
+        eprintln!("skipping, `{}` not found", compiler.name);
+    }
+    found
+}
+
+/// Compile a program to `binary` for `target` with bfc, and then with
+/// `compiler` if bfc doesn't write a binary itself. `args` are the rest of
+/// bfc's arguments, including the program.
+fn compile<S: AsRef<OsStr>>(
+    target: &str,
+    compiler: Option<&Compiler>,
+    args: impl IntoIterator<Item = S>,
+    binary: &Path,
+) {
+    let compiled = match compiler {
+        Some(compiler) => binary.with_extension(compiler.extension),
+        None => binary.to_path_buf(),
+    };
+    let status = Command::new(env!("CARGO_BIN_EXE_bfc"))
+        .args(["compile", "--target", target, "-o"])
+        .arg(&compiled)
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    if let Some(compiler) = compiler {
+        let status = Command::new(compiler.name)
+            .args(compiler.flags)
+            .arg("-o")
+            .arg(binary)
+            .arg(&compiled)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{} failed to compile", compiled.display());
+        fs::remove_file(compiled).unwrap();
+    }
+}
+
+/// Compile every example to `target`, checking the binary writes what
+/// `bfc run` does. Skipped if the compiler isn't installed.
+fn check_backend(target: &str, compiler: Option<&Compiler>) {
+    if !installed(compiler) {
+        return;
+    }
+
+    let bfc = env!("CARGO_BIN_EXE_bfc");
+    for example in examples() {
+        let name = example.file_stem().unwrap().to_str().unwrap();
+        let binary = env::temp_dir().join(format!("bfc-{}-{name}-{target}", std::process::id()));
+        compile(target, compiler, [&example], &binary);
+
+        // The examples never halt, so compare the start of their output.
+        let expected = first_output(Command::new(bfc).arg(&example), 4096);
+        assert_eq!(first_output(&mut Command::new(&binary), 4096), expected);
+
+        fs::remove_file(binary).unwrap();
+    }
+}
+
+/// Compile small programs to `target` with every cell width and a range of
+/// options, checking the binary exits, writes and dumps what `bfc run`
+/// does. Skipped if the compiler isn't installed.
+fn check_options(target: &str, compiler: Option<&Compiler>) {
+    if !installed(compiler) {
+        return;
+    }
+
+    // Cells that need the full width: 16^k, and products past a byte.
+    let powers = format!("+{}", "[>++++++++++++++++<-]>#".repeat(16));
+    let cases: &[(&[&str], &str, &[u8])] = &[
+        (&[], &powers, b""),
+        (&[], "-#>+++[>-----<-]>#<<#", b""),
+        (&[], &format!("{}#.[>+>++<<-]>.>.#", "+".repeat(300)), b""),
+        (&[], "++>>+++<<[-]+++#>>[-]#", b""),
+        (&["--eof", "zero"], ",[.,]", b"hello"),
+        (&["--eof", "unchanged"], "+++,#", b""),
+        (&["--eof", "zero"], "+++,#", b""),
+        (&["--eof", "minus-one"], "+++,#", b""),
+        (&["--eof", "minus-one"], ",+[-.,+]", b"abc"),
+        (&["--eof", "zero"], ">>,<<+[>>.<<-]>>>,.", b"x"),
+        (&["--tape-size", "4"], ">>>>", b""),
+        (&["--tape-size", "4"], "+<", b""),
+        (&["--tape-size", "4"], "+[>+]", b""),
+        (&["--tape-size", "4"], "+[<+]", b""),
+        (&["--tape-size", "4"], ">+[>>>+<<<-]", b""),
+        (&["--tape-size", "4"], "+[>>>>.<<<<-]", b""),
+        (&["--tape-size", "4"], ">>>+[<<<<+>>>>-]", b""),
+    ];
+
+    let bfc = env!("CARGO_BIN_EXE_bfc");
+    let binary = env::temp_dir().join(format!("bfc-{}-options-{target}", std::process::id()));
+    for width in ["8", "16", "32", "64"] {
+        for &(flags, source, input) in cases {
+            let options = [&["--debug-dump", "--cell-width", width][..], flags].concat();
+            compile(
+                target,
+                compiler,
+                options.iter().chain(&["-e", source]),
+                &binary,
+            );
+
+            let (code, stdout, stderr) =
+                output_with_input(Command::new(bfc).args(&options).args(["-e", source]), input);
+            let expected = (code, stdout, without_location(&stderr));
+            let (code, stdout, stderr) = output_with_input(&mut Command::new(&binary), input);
+            let output = (code, stdout, without_location(&stderr));
+            assert_eq!(output, expected, "{source} with {options:?}");
+        }
+    }
+    fs::remove_file(binary).unwrap();
+}
+
+/// Run a command with `input` on stdin, returning its exit code, stdout and
+/// stderr.
+fn output_with_input(command: &mut Command, input: &[u8]) -> (Option<i32>, Vec<u8>, String) {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    (output.status.code(), output.stdout, stderr)
+}
+
+/// `stderr` with any tape error cut down to its message: compiled programs
+/// report the op it happened at, while `bfc run` shows the source.
+fn without_location(stderr: &str) -> String {
+    let mut lines = Vec::new();
+    for line in stderr.lines() {
+        if let Some(message) = line.strip_prefix("Error: ") {
+            let (kind, rest) = message.split_once(" at op ").unwrap();
+            let (_, rest) = rest.split_once(": ").unwrap();
+            lines.push(format!("error: {kind}: {}", rest.trim_end_matches('.')));
+            break;
+        }
+        lines.push(line.to_string());
+        if line.starts_with("error: ") {
+            break;
+        }
+    }
+    lines.join("\n")
+}
+
+#[test]
+fn test_compile_c() {
+    let cc = Compiler {
+        name: "cc",
+        extension: "c",
+        flags: &["-O1", "-Wall", "-Werror"],
+    };
+    check_backend("c", Some(&cc));
+    check_options("c", Some(&cc));
+}
+
+#[test]
+fn test_compile_rust() {
+    let rustc = Compiler {
+        name: "rustc",
+        extension: "rs",
+        flags: &["--edition", "2024", "-D", "warnings"],
+    };
+    check_backend("rust", Some(&rustc));
+    check_options("rust", Some(&rustc));
+}
+
+#[test]
+fn test_compile_x86_64() {
+    if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        return;
+    }
+    let cc = Compiler {
+        name: "cc",
+        extension: "s",
+        flags: &["-nostdlib", "-static"],
+    };
+    check_backend("x86_64", Some(&cc));
+    check_options("x86_64", Some(&cc));
+}
+
+#[test]
+fn test_compile_elf() {
+    if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        return;
+    }
+    check_backend("elf", None);
+    check_options("elf", None);
+}